@@ -1,11 +1,19 @@
 use std::rc::Rc;
 use std::sync::{
-    atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst},
-    Arc,
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering::SeqCst},
+    Arc, Mutex,
 };
-use std::thread::{spawn, JoinHandle};
+use std::thread::{park_timeout, spawn, JoinHandle};
 use std::time::{Duration, Instant};
 
+mod midi;
+pub use self::midi::MidiClock;
+
+#[cfg(all(target_os = "linux", feature = "timerfd"))]
+mod timerfd;
+#[cfg(all(target_os = "linux", feature = "timerfd"))]
+pub use self::timerfd::TimerFdClock;
+
 pub trait Clock {
     // start clock
     fn start(&mut self);
@@ -22,6 +30,16 @@ pub trait Clock {
     // set the interval between ticks
     fn with_period(&mut self, period: Duration) -> &mut Self;
 
+    // change the interval between ticks. takes effect at the next tick while running.
+    fn set_period(&mut self, period: Duration);
+
+    // gradually change the interval between ticks, recomputing it on every tick.
+    // clocks that cannot ramp jump straight to the new period.
+    fn ramp_period(&mut self, period: Duration, ticks: usize, curve: Curve) {
+        let _ = (ticks, curve);
+        self.set_period(period);
+    }
+
     // register a callback to be called on each clock tick
     fn on_tick<F>(&mut self, callback: F) -> &mut Self
     where
@@ -32,28 +50,240 @@ pub trait Clock {
 
     // get current period
     fn get_period(&self) -> Duration;
+
+    // returns true if the ticks follow an outside source, such as a MIDI
+    // master, that sets the position on its own
+    fn is_external(&self) -> bool {
+        false
+    }
+
+    // get timing measurements since the clock was created or reset.
+    // clocks that do not measure timing return all zeros.
+    fn stats(&self) -> Stats {
+        Stats::default()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+// timing measurements of a clock worker thread
+pub struct Stats {
+    pub ticks: usize,            // ticks measured
+    pub missed: usize,           // ticks that fired a period or more late, or were skipped
+    pub last_lateness: Duration, // delay between when the last tick was due and when it fired
+    pub mean_lateness: Duration, // average delay, shows drift
+    pub max_lateness: Duration,  // largest delay, or jitter
+    pub mean_callback: Duration, // average time spent in the on tick callback
+    pub max_callback: Duration,  // longest time spent in the on tick callback
+}
+
+#[derive(Clone, Default)]
+// collects Stats from a worker thread without locking
+struct Recorder {
+    ticks: Arc<AtomicUsize>,
+    missed: Arc<AtomicUsize>,
+    last_lateness: Arc<AtomicU64>, // nanoseconds
+    total_lateness: Arc<AtomicU64>,
+    max_lateness: Arc<AtomicU64>,
+    total_callback: Arc<AtomicU64>,
+    max_callback: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    // record a tick that fired late and how long its callback ran
+    fn tick(&self, lateness: Duration, callback: Duration) {
+        let lateness = lateness.as_nanos() as u64;
+        let callback = callback.as_nanos() as u64;
+        self.last_lateness.store(lateness, SeqCst);
+        self.total_lateness.fetch_add(lateness, SeqCst);
+        self.max_lateness.fetch_max(lateness, SeqCst);
+        self.total_callback.fetch_add(callback, SeqCst);
+        self.max_callback.fetch_max(callback, SeqCst);
+        self.ticks.fetch_add(1, SeqCst);
+    }
+
+    // record ticks that were skipped
+    fn miss(&self, ticks: usize) {
+        self.missed.fetch_add(ticks, SeqCst);
+    }
+
+    fn get(&self) -> Stats {
+        let ticks = self.ticks.load(SeqCst);
+        let mean = |total: &AtomicU64| {
+            Duration::from_nanos(total.load(SeqCst).checked_div(ticks as u64).unwrap_or(0))
+        };
+        Stats {
+            ticks,
+            missed: self.missed.load(SeqCst),
+            last_lateness: Duration::from_nanos(self.last_lateness.load(SeqCst)),
+            mean_lateness: mean(&self.total_lateness),
+            max_lateness: Duration::from_nanos(self.max_lateness.load(SeqCst)),
+            mean_callback: mean(&self.total_callback),
+            max_callback: Duration::from_nanos(self.max_callback.load(SeqCst)),
+        }
+    }
+
+    fn clear(&self) {
+        for counter in &[&self.ticks, &self.missed] {
+            counter.store(0, SeqCst);
+        }
+        for nanos in &[
+            &self.last_lateness,
+            &self.total_lateness,
+            &self.max_lateness,
+            &self.total_callback,
+            &self.max_callback,
+        ] {
+            nanos.store(0, SeqCst);
+        }
+    }
+}
+
+// shared on tick callback
+type TickCallback = Arc<dyn Fn(usize) + Send + Sync>;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+// shape of a gradual period change. applied to the tick rate, so a linear
+// curve is a linear change in tempo.
+pub enum Curve {
+    Linear,      // tick rate changes by the same amount on every tick
+    Exponential, // tick rate changes by the same ratio on every tick
+}
+
+// an in progress period change
+struct Ramp {
+    from: f64,      // start rate in ticks per second
+    to: f64,        // target rate in ticks per second
+    ticks: usize,   // length of the change
+    elapsed: usize, // ticks since the change started
+    curve: Curve,   // shape of the change
+}
+
+impl Ramp {
+    fn period(&self) -> Duration {
+        let progress = self.elapsed as f64 / self.ticks as f64;
+        let rate = match self.curve {
+            Curve::Linear => self.from + (self.to - self.from) * progress,
+            Curve::Exponential => self.from * (self.to / self.from).powf(progress),
+        };
+        Duration::from_secs_f64(1.0 / rate)
+    }
+}
+
+#[derive(Clone)]
+// a period that a worker thread can read on every tick while it is changed
+struct Period {
+    nanos: Arc<AtomicU64>,          // current period
+    ramp: Arc<Mutex<Option<Ramp>>>, // in progress period change
+}
+
+impl Period {
+    fn new(period: Duration) -> Self {
+        Self {
+            nanos: Arc::new(AtomicU64::new(period.as_nanos() as u64)),
+            ramp: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn get(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(SeqCst))
+    }
+
+    // set period right away and cancel any ramp
+    fn set(&self, period: Duration) {
+        let mut ramp = self.ramp.lock().unwrap();
+        *ramp = None;
+        self.nanos.store(period.as_nanos() as u64, SeqCst);
+    }
+
+    // move from the current period to a new period over a number of ticks
+    fn ramp(&self, period: Duration, ticks: usize, curve: Curve) {
+        if ticks == 0 || period.as_nanos() == 0 {
+            return self.set(period);
+        }
+        let mut ramp = self.ramp.lock().unwrap();
+        *ramp = Some(Ramp {
+            from: 1.0 / self.get().as_secs_f64(),
+            to: 1.0 / period.as_secs_f64(),
+            ticks,
+            elapsed: 0,
+            curve,
+        });
+    }
+
+    // called by a clock after every tick to step an in progress ramp
+    fn tick(&self) {
+        // never block the clock thread. if the ramp is being changed, the
+        // current period is kept until the next tick.
+        if let Ok(mut ramp) = self.ramp.try_lock() {
+            if let Some(progress) = ramp.as_mut() {
+                progress.elapsed += 1;
+                self.nanos
+                    .store(progress.period().as_nanos() as u64, SeqCst);
+                if progress.elapsed >= progress.ticks {
+                    *ramp = None;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+// how the SystemClock worker thread waits for the next tick
+pub enum Mode {
+    // poll the system time without yielding. lowest jitter, but saturates a CPU core.
+    Spin,
+    // sleep until the spin window before the next tick, then poll for the remainder.
+    Hybrid(Duration),
 }
 
 #[derive(Clone)]
 // a clock source based on polling OS system time
 pub struct SystemClock {
-    callback: Arc<dyn Fn(usize) + Send + Sync>, // on tick callback
-    handle: Rc<Option<JoinHandle<()>>>,         // worker thread wrapped in Rc for Clone
-    period: Duration,                           // duration between clock ticks
-    running: Arc<AtomicBool>,                   // clock state
-    ticks: Arc<AtomicUsize>,                    // number of ticks since clock start
+    callback: TickCallback,             // on tick callback
+    handle: Rc<Option<JoinHandle<()>>>, // worker thread wrapped in Rc for Clone
+    mode: Mode,                         // how to wait between ticks
+    period: Period,                     // duration between clock ticks
+    running: Arc<AtomicBool>,           // clock state
+    stats: Recorder,                    // timing measurements
+    ticks: Arc<AtomicUsize>,            // number of ticks since clock start
 }
 
 impl SystemClock {
+    // a spin window that absorbs typical desktop OS sleep overshoot
+    pub const DEFAULT_SPIN: Duration = Duration::from_millis(2);
+
     pub fn new() -> Self {
         Self::default()
     }
 
+    pub fn with_mode(&mut self, mode: Mode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
     pub fn build(&self) -> Self {
-        let mut clock = Self::default();
-        clock.callback = self.callback.clone();
-        clock.period = self.period;
-        clock
+        Self {
+            callback: self.callback.clone(),
+            mode: self.mode,
+            period: Period::new(self.period.get()),
+            ..Self::default()
+        }
+    }
+
+    pub fn get_mode(&self) -> Mode {
+        self.mode
+    }
+
+    // get the largest delay between when a tick was due and when it fired
+    pub fn get_jitter(&self) -> Duration {
+        self.stats().max_lateness
+    }
+
+    // wake worker thread if it is parked in hybrid mode, so it reads a new period
+    fn wake(&self) {
+        if let Some(handle) = self.handle.as_ref() {
+            handle.thread().unpark();
+        }
     }
 }
 
@@ -62,8 +292,10 @@ impl Default for SystemClock {
         Self {
             callback: Arc::new(|_| {}),
             handle: Rc::new(Option::None),
-            period: Duration::from_secs(1),
+            mode: Mode::Spin,
+            period: Period::new(Duration::from_secs(1)),
             running: Arc::new(AtomicBool::new(false)),
+            stats: Recorder::default(),
             ticks: Arc::new(AtomicUsize::new(0)),
         }
     }
@@ -81,7 +313,9 @@ impl Clock for SystemClock {
         // variables to move into closure
         let running = self.running.clone();
         let ticks = self.ticks.clone();
-        let period = self.period;
+        let stats = self.stats.clone();
+        let mode = self.mode;
+        let shared_period = self.period.clone();
         let callback = self.callback.clone();
 
         self.handle = Rc::new(Some(spawn(move || {
@@ -92,6 +326,9 @@ impl Clock for SystemClock {
             // total number of ticks that have occurred and the value is
             // accurate for the duration of the current period.
             callback(ticks.fetch_add(1, SeqCst) + 1);
+            stats.tick(Duration::from_secs(0), now.elapsed());
+            shared_period.tick();
+            let mut last = shared_period.get(); // period the last tick was timed with
             while running.load(SeqCst) {
                 // This is an inefficient way of keeping time. Polling the
                 // system time without yielding will saturate a CPU core. It
@@ -99,12 +336,43 @@ impl Clock for SystemClock {
                 // hardware timer. However, I think this is the best cross-
                 // platform way to generate tick events at regular intervals
                 // using only the standard library.
-                if now.elapsed() >= period {
-                    now = now.checked_add(period).unwrap_or_else(Instant::now);
+                // The period is read on every pass so that changes take
+                // effect at the next tick without restarting the clock.
+                let period = shared_period.get();
+                let elapsed = now.elapsed();
+                if elapsed >= period {
+                    let lateness = elapsed - period;
+                    if lateness >= period && elapsed < last {
+                        // The period was shortened before the old one was up,
+                        // so the shorter ticks in between were never due.
+                        // Skip them instead of firing them in a burst.
+                        stats.miss((lateness.as_nanos() / period.as_nanos().max(1)) as usize);
+                        now = Instant::now();
+                    } else {
+                        // Deadlines stay on the wall clock. After a slow
+                        // callback, the missed ticks fire back to back until
+                        // the clock has caught up.
+                        if lateness >= period {
+                            stats.miss(1);
+                        }
+                        now = now.checked_add(period).unwrap_or_else(Instant::now);
+                    }
+                    last = period;
                     // This callback must return before the next tick, otherwise
                     // future ticks will be consistently behind for the rest of
                     // execution.
+                    let called = Instant::now();
                     callback(ticks.fetch_add(1, SeqCst) + 1);
+                    stats.tick(lateness, called.elapsed());
+                    shared_period.tick();
+                } else if let Mode::Hybrid(spin) = mode {
+                    // The OS may oversleep, so wake up a spin window early and
+                    // poll for the last stretch. stop() and period changes
+                    // unpark this thread.
+                    let remaining = period - elapsed;
+                    if remaining > spin {
+                        park_timeout(remaining - spin);
+                    }
                 }
             }
         })));
@@ -114,6 +382,8 @@ impl Clock for SystemClock {
         self.running.store(false, SeqCst);
         if let Some(reference) = Rc::get_mut(&mut self.handle) {
             if let Some(handle) = reference.take() {
+                // wake worker thread if it is parked in hybrid mode
+                handle.thread().unpark();
                 // consume handle and replace with None.
                 // only panics if worker thread panics.
                 // blocks here until clock is stopped.
@@ -124,6 +394,7 @@ impl Clock for SystemClock {
 
     fn reset(&mut self) {
         self.ticks.store(0, SeqCst);
+        self.stats.clear();
     }
 
     fn is_running(&self) -> bool {
@@ -131,10 +402,20 @@ impl Clock for SystemClock {
     }
 
     fn with_period(&mut self, period: Duration) -> &mut Self {
-        self.period = period;
+        self.period.set(period);
         self
     }
 
+    fn set_period(&mut self, period: Duration) {
+        self.period.set(period);
+        self.wake();
+    }
+
+    fn ramp_period(&mut self, period: Duration, ticks: usize, curve: Curve) {
+        self.period.ramp(period, ticks, curve);
+        self.wake();
+    }
+
     fn on_tick<F>(&mut self, callback: F) -> &mut Self
     where
         F: Fn(usize) + Send + Sync + 'static,
@@ -148,13 +429,112 @@ impl Clock for SystemClock {
     }
 
     fn get_period(&self) -> Duration {
-        self.period
+        self.period.get()
+    }
+
+    fn stats(&self) -> Stats {
+        self.stats.get()
+    }
+}
+
+#[derive(Clone)]
+// a clock source that only ticks when advanced by hand, for offline and test use.
+// clones share state, so a handle can be kept to advance a clock owned elsewhere.
+pub struct ManualClock {
+    callback: Arc<Mutex<TickCallback>>, // on tick callback shared between clones
+    period: Period,                     // nominal duration between ticks
+    running: Arc<AtomicBool>,           // clock state
+    ticks: Arc<AtomicUsize>,            // number of ticks since clock start
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn build(&self) -> Self {
+        self.clone()
+    }
+
+    // fire n ticks in a row on the calling thread. does nothing while stopped.
+    // returns the number of ticks since clock start.
+    pub fn advance(&self, n: usize) -> usize {
+        // clone the callback out of the lock so it can re-register itself
+        let callback = self.callback.lock().unwrap().clone();
+        for _ in 0..n {
+            if !self.running.load(SeqCst) {
+                break;
+            }
+            callback(self.ticks.fetch_add(1, SeqCst) + 1);
+            self.period.tick();
+        }
+        self.ticks.load(SeqCst)
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self {
+            callback: Arc::new(Mutex::new(Arc::new(|_| {}))),
+            period: Period::new(Duration::from_secs(1)),
+            running: Arc::new(AtomicBool::new(false)),
+            ticks: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl Clock for ManualClock {
+    // unlike SystemClock, starting does not fire a tick. call advance() instead.
+    fn start(&mut self) {
+        self.running.store(true, SeqCst);
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, SeqCst);
+    }
+
+    fn reset(&mut self) {
+        self.ticks.store(0, SeqCst);
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(SeqCst)
+    }
+
+    fn with_period(&mut self, period: Duration) -> &mut Self {
+        self.period.set(period);
+        self
+    }
+
+    fn set_period(&mut self, period: Duration) {
+        self.period.set(period);
+    }
+
+    fn ramp_period(&mut self, period: Duration, ticks: usize, curve: Curve) {
+        self.period.ramp(period, ticks, curve);
+    }
+
+    fn on_tick<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        *self.callback.lock().unwrap() = Arc::new(callback);
+        self
+    }
+
+    fn get_ticks(&self) -> usize {
+        self.ticks.load(SeqCst)
+    }
+
+    fn get_period(&self) -> Duration {
+        self.period.get()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::mpsc::channel;
     use std::thread::sleep;
 
     #[test]
@@ -162,10 +542,10 @@ mod tests {
         let mut clock = SystemClock::new();
         clock.start();
         clock.start();
-        assert_eq!(clock.is_running(), true);
+        assert!(clock.is_running());
         clock.stop();
         clock.stop();
-        assert_eq!(clock.is_running(), false);
+        assert!(!clock.is_running());
         clock.reset();
         clock.reset();
         assert_eq!(clock.get_ticks(), 0);
@@ -175,32 +555,218 @@ mod tests {
     fn count_ticks() {
         let count = 10;
         let period = Duration::from_millis(10);
+        let (sender, receiver) = channel();
         let x = Arc::new(AtomicUsize::new(0));
         let y = x.clone();
         let mut clock = SystemClock::new()
             .with_period(period)
-            .on_tick(move |_| {
+            .on_tick(move |tick| {
                 let _ = y.fetch_add(1, SeqCst);
+                sender.send(tick).unwrap();
             })
             .build();
+        let now = Instant::now();
         clock.start();
-        sleep(count * period - period / 2);
+        for tick in 1..=count {
+            assert_eq!(receiver.recv_timeout(period * 100), Ok(tick as usize));
+        }
+        // the first tick is immediate, and no tick fires before it is due
+        assert!(now.elapsed() >= (count - 1) * period);
         clock.stop();
-        assert_eq!(count as usize, clock.get_ticks());
-        assert_eq!(count as usize, x.load(SeqCst));
+        assert!(clock.get_ticks() >= count as usize);
+        assert_eq!(clock.get_ticks(), x.load(SeqCst));
     }
 
     #[test]
     fn stop_start() {
         let period = Duration::from_millis(10);
-        let mut clock = SystemClock::new().with_period(period).build();
+        let (sender, receiver) = channel();
+        let mut clock = SystemClock::new()
+            .with_period(period)
+            .on_tick(move |tick| sender.send(tick).unwrap())
+            .build();
         clock.start();
-        sleep(3 * period / 2);
+        assert_eq!(receiver.recv_timeout(period * 100), Ok(1));
+        assert_eq!(receiver.recv_timeout(period * 100), Ok(2));
         clock.stop();
         let last_tick = clock.get_ticks();
-        clock.on_tick(move |tick| assert_eq!(last_tick + 1, tick));
+        assert_eq!(receiver.try_iter().last().unwrap_or(2), last_tick);
+        // counting carries on from where the clock was stopped
+        clock.start();
+        assert_eq!(receiver.recv_timeout(period * 100), Ok(last_tick + 1));
+        clock.stop();
+    }
+
+    #[test]
+    fn set_period() {
+        let period = Duration::from_secs(1);
+        let times = Arc::new(Mutex::new(Vec::new()));
+        let log = times.clone();
+        let mut clock = SystemClock::new()
+            .with_period(period)
+            .on_tick(move |_| log.lock().unwrap().push(Instant::now()))
+            .build();
         clock.start();
-        sleep(period / 2);
+        sleep(period / 20);
+        // a running clock picks up the new period without a restart
+        clock.set_period(period / 100);
+        while clock.get_ticks() < 5 {
+            sleep(period / 100);
+        }
         clock.stop();
+        assert_eq!(clock.get_period(), period / 100);
+        // missed ticks are skipped rather than fired in a burst
+        let times = times.lock().unwrap();
+        assert!(times[1..]
+            .windows(2)
+            .all(|pair| pair[1] - pair[0] >= period / 200));
+    }
+
+    #[test]
+    fn hybrid_set_period() {
+        // a parked worker thread picks up a shorter period without waiting out the old one
+        let period = Duration::from_secs(10);
+        let (sender, receiver) = channel();
+        let mut clock = SystemClock::new()
+            .with_period(period)
+            .with_mode(Mode::Hybrid(SystemClock::DEFAULT_SPIN))
+            .on_tick(move |tick| sender.send(tick).unwrap())
+            .build();
+        let now = Instant::now();
+        clock.start();
+        assert_eq!(receiver.recv_timeout(period), Ok(1));
+        sleep(Duration::from_millis(10));
+        clock.set_period(Duration::from_millis(10));
+        assert_eq!(receiver.recv_timeout(period), Ok(2));
+        clock.stop();
+        assert!(now.elapsed() < period / 2);
+    }
+
+    #[test]
+    fn ramp_period() {
+        let from = Duration::from_millis(10);
+        let to = Duration::from_millis(5);
+        let mut clock = ManualClock::new().with_period(from).build();
+        clock.start();
+        clock.ramp_period(to, 4, Curve::Linear);
+        assert_eq!(clock.get_period(), from);
+        clock.advance(2); // half way there in ticks per second
+        let rate = 1.0 / clock.get_period().as_secs_f64();
+        assert!((rate - 150.0).abs() < 0.01);
+        clock.advance(2);
+        assert_eq!(clock.get_period(), to);
+        clock.advance(2); // stays at target once the ramp is done
+        assert_eq!(clock.get_period(), to);
+
+        clock.ramp_period(from, 4, Curve::Exponential);
+        clock.advance(2); // geometric mean of rates
+        let rate = 1.0 / clock.get_period().as_secs_f64();
+        assert!((rate - 20000.0_f64.sqrt()).abs() < 0.01);
+        clock.set_period(to); // cancels ramp
+        clock.advance(2);
+        assert_eq!(clock.get_period(), to);
+    }
+
+    #[test]
+    fn hybrid_ticks() {
+        let count = 10;
+        let period = Duration::from_millis(10);
+        let (sender, receiver) = channel();
+        let mut clock = SystemClock::new()
+            .with_period(period)
+            .with_mode(Mode::Hybrid(SystemClock::DEFAULT_SPIN))
+            .on_tick(move |_| sender.send(Instant::now()).unwrap())
+            .build();
+        assert_eq!(clock.get_mode(), Mode::Hybrid(SystemClock::DEFAULT_SPIN));
+        clock.start();
+        let times: Vec<Instant> = receiver.iter().take(count as usize).collect();
+        clock.stop();
+        // sleeping between ticks keeps time within a period over the run
+        let elapsed = times[times.len() - 1] - times[0];
+        assert!(elapsed >= (count - 1) * period);
+        assert!(elapsed <= count * period);
+        assert_eq!(clock.stats().ticks, clock.get_ticks()); // every tick is measured
+        clock.reset();
+        assert_eq!(clock.get_jitter(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn stats() {
+        let period = Duration::from_millis(10);
+        let mut clock = SystemClock::new()
+            .with_period(period)
+            .with_mode(Mode::Hybrid(SystemClock::DEFAULT_SPIN))
+            .on_tick(move |tick| {
+                if tick == 2 {
+                    sleep(period * 3); // miss a few deadlines
+                }
+            })
+            .build();
+        clock.start();
+        while clock.get_ticks() < 4 {
+            sleep(period);
+        }
+        clock.stop();
+        let stats = clock.stats();
+        assert!(stats.ticks > 0);
+        assert!(stats.missed >= 1);
+        assert!(stats.max_callback >= period * 3);
+        assert!(stats.max_lateness >= period); // the tick after the stall
+        assert!(stats.mean_lateness <= stats.max_lateness);
+        assert_eq!(stats.max_lateness, clock.get_jitter());
+        clock.reset();
+        assert_eq!(clock.stats(), Stats::default());
+        assert_eq!(ManualClock::new().stats(), Stats::default()); // not measured
+    }
+
+    #[test]
+    fn hybrid_stop() {
+        // a parked worker thread should not hold up stop() for a whole period
+        let period = Duration::from_secs(10);
+        let mut clock = SystemClock::new()
+            .with_period(period)
+            .with_mode(Mode::Hybrid(SystemClock::DEFAULT_SPIN))
+            .build();
+        let now = Instant::now();
+        clock.start();
+        sleep(Duration::from_millis(10));
+        clock.stop();
+        assert!(now.elapsed() < period / 2);
+        assert_eq!(1, clock.get_ticks());
+    }
+
+    #[test]
+    fn manual_ticks() {
+        let x = Arc::new(AtomicUsize::new(0));
+        let y = x.clone();
+        let mut clock = ManualClock::new()
+            .on_tick(move |tick| assert_eq!(y.fetch_add(1, SeqCst) + 1, tick))
+            .build();
+        assert_eq!(clock.advance(5), 0); // stopped clocks do not tick
+        clock.start();
+        assert_eq!(clock.advance(5), 5);
+        clock.stop();
+        assert_eq!(clock.advance(5), 5);
+        clock.start();
+        assert_eq!(clock.advance(5), 10);
+        assert_eq!(x.load(SeqCst), 10);
+    }
+
+    #[test]
+    fn manual_handle() {
+        let handle = ManualClock::new();
+        let mut clock = handle.clone();
+        let x = Arc::new(AtomicUsize::new(0));
+        let y = x.clone();
+        clock.on_tick(move |_| {
+            let _ = y.fetch_add(1, SeqCst);
+        });
+        clock.start();
+        handle.advance(3);
+        assert!(handle.is_running());
+        assert_eq!(clock.get_ticks(), 3);
+        assert_eq!(x.load(SeqCst), 3);
+        clock.reset();
+        assert_eq!(handle.get_ticks(), 0);
     }
 }
@@ -0,0 +1,319 @@
+use super::{Clock, Period, TickCallback};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst},
+    Arc, Mutex,
+};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+// MIDI status bytes this clock responds to
+const TIMING_CLOCK: u8 = 0xF8;
+const START: u8 = 0xFA;
+const CONTINUE: u8 = 0xFB;
+const STOP: u8 = 0xFC;
+const SONG_POSITION: u8 = 0xF2;
+
+// MIDI beat clock is sent 24 times per quarter note, or 6 times per 16th note
+const PULSES_PER_MIDI_BEAT: usize = 6;
+
+// weight of each new interval in the tempo estimate
+const SMOOTHING: u32 = 8;
+
+// parser state owned by the reader thread
+#[derive(Default)]
+struct Parser {
+    last_clock: Option<Instant>, // time of the previous timing clock
+    position: Option<Vec<u8>>,   // song position data bytes received so far
+}
+
+#[derive(Clone)]
+// a clock source that follows MIDI beat clock from a raw MIDI byte stream.
+// ticks at 24 PPQN while the master is playing, so use it with a 24 PPQN sequencer.
+pub struct MidiClock {
+    callback: Arc<Mutex<TickCallback>>, // on tick callback shared with the reader thread
+    input: Arc<Mutex<Option<Box<dyn Read + Send>>>>, // taken by the reader thread
+    period: Period,                     // estimated duration between clock ticks
+    playing: Arc<AtomicBool>,           // master transport state
+    running: Arc<AtomicBool>,           // clock state
+    ticks: Arc<AtomicUsize>,            // number of ticks since master start
+}
+
+impl MidiClock {
+    // follow a byte stream, such as a pipe or serial port
+    pub fn from_reader<R: Read + Send + 'static>(reader: R) -> Self {
+        Self {
+            callback: Arc::new(Mutex::new(Arc::new(|_| {}))),
+            input: Arc::new(Mutex::new(Some(Box::new(reader)))),
+            period: Period::new(Duration::from_secs(1)),
+            playing: Arc::new(AtomicBool::new(false)),
+            running: Arc::new(AtomicBool::new(false)),
+            ticks: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // follow a file or device, such as /dev/midi1
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::from_reader(File::open(path)?))
+    }
+
+    pub fn build(&self) -> Self {
+        self.clone()
+    }
+
+    // returns true if the master has started or continued and not stopped
+    pub fn is_playing(&self) -> bool {
+        self.playing.load(SeqCst)
+    }
+
+    // handle one byte of the stream
+    fn receive(&self, parser: &mut Parser, byte: u8, now: Instant) {
+        match byte {
+            TIMING_CLOCK => {
+                // clocks read together share a time, so they say nothing about tempo
+                let interval = parser
+                    .last_clock
+                    .map(|last| now.saturating_duration_since(last));
+                if let Some(interval) = interval.filter(|interval| !interval.is_zero()) {
+                    // moving average of intervals smooths out transport jitter
+                    let estimate = self.period.get();
+                    if interval > estimate {
+                        self.period
+                            .set(estimate + (interval - estimate) / SMOOTHING);
+                    } else {
+                        self.period
+                            .set(estimate - (estimate - interval) / SMOOTHING);
+                    }
+                }
+                parser.last_clock = Some(now);
+                if self.playing.load(SeqCst) {
+                    // held through the callback, so stop() can wait for it
+                    let callback = self.callback.lock().unwrap();
+                    if self.running.load(SeqCst) {
+                        callback(self.ticks.fetch_add(1, SeqCst) + 1);
+                    }
+                }
+            }
+            START => {
+                self.ticks.store(0, SeqCst);
+                self.playing.store(true, SeqCst);
+            }
+            CONTINUE => self.playing.store(true, SeqCst),
+            STOP => self.playing.store(false, SeqCst),
+            SONG_POSITION => parser.position = Some(Vec::with_capacity(2)),
+            0xF9..=0xFF => {} // other real time messages may appear anywhere
+            0x80..=0xF7 => parser.position = None, // any other status byte
+            _ => {
+                // data byte, only song position data is of interest
+                if let Some(data) = parser.position.as_mut() {
+                    data.push(byte);
+                    if data.len() == 2 {
+                        // position is in 16th notes, lsb first
+                        let position = data[0] as usize | (data[1] as usize) << 7;
+                        self.ticks.store(position * PULSES_PER_MIDI_BEAT, SeqCst);
+                        parser.position = None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Clock for MidiClock {
+    // begin following the stream. the reader thread is spawned on the first
+    // start and runs until the stream ends, since a blocking read cannot be
+    // interrupted. ticks only fire while the master is playing.
+    fn start(&mut self) {
+        self.running.store(true, SeqCst);
+        if let Some(mut input) = self.input.lock().unwrap().take() {
+            let clock = self.clone();
+            spawn(move || {
+                let mut parser = Parser::default();
+                let mut buf = [0u8; 64];
+                while let Ok(len) = input.read(&mut buf) {
+                    if len == 0 {
+                        break; // end of stream
+                    }
+                    let now = Instant::now();
+                    for byte in &buf[..len] {
+                        clock.receive(&mut parser, *byte, now);
+                    }
+                }
+            });
+        }
+    }
+
+    // stop following the stream. returns after a tick that is in progress.
+    fn stop(&mut self) {
+        self.running.store(false, SeqCst);
+        drop(self.callback.lock().unwrap());
+    }
+
+    fn reset(&mut self) {
+        self.ticks.store(0, SeqCst);
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(SeqCst)
+    }
+
+    // sets the tempo estimate until the first clock messages arrive
+    fn with_period(&mut self, period: Duration) -> &mut Self {
+        self.period.set(period);
+        self
+    }
+
+    // the master sets the tempo, so this is replaced by the next estimate
+    fn set_period(&mut self, period: Duration) {
+        self.period.set(period);
+    }
+
+    fn on_tick<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        *self.callback.lock().unwrap() = Arc::new(callback);
+        self
+    }
+
+    fn get_ticks(&self) -> usize {
+        self.ticks.load(SeqCst)
+    }
+
+    fn get_period(&self) -> Duration {
+        self.period.get()
+    }
+
+    // the master starts, continues, and moves the position
+    fn is_external(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{empty, Cursor};
+    use std::sync::mpsc::channel;
+    use std::thread::sleep;
+
+    #[test]
+    fn transport() {
+        let x = Arc::new(AtomicUsize::new(0));
+        let y = x.clone();
+        let mut clock = MidiClock::from_reader(empty())
+            .on_tick(move |_| {
+                let _ = y.fetch_add(1, SeqCst);
+            })
+            .build();
+        let mut parser = Parser::default();
+        let now = Instant::now();
+        clock.start();
+        clock.receive(&mut parser, TIMING_CLOCK, now); // not playing yet
+        assert_eq!(clock.get_ticks(), 0);
+        for byte in &[START, TIMING_CLOCK, TIMING_CLOCK, STOP, TIMING_CLOCK] {
+            clock.receive(&mut parser, *byte, now);
+        }
+        assert!(!clock.is_playing());
+        assert_eq!(clock.get_ticks(), 2);
+        for byte in &[CONTINUE, TIMING_CLOCK] {
+            clock.receive(&mut parser, *byte, now);
+        }
+        assert!(clock.is_playing());
+        assert_eq!(clock.get_ticks(), 3);
+        clock.stop(); // following is paused, but the master keeps playing
+        clock.receive(&mut parser, TIMING_CLOCK, now);
+        assert_eq!(clock.get_ticks(), 3);
+        assert_eq!(x.load(SeqCst), 3);
+    }
+
+    #[test]
+    fn song_position() {
+        let clock = MidiClock::from_reader(empty());
+        let mut parser = Parser::default();
+        let now = Instant::now();
+        // position 130 is 0x02 0x01, with a clock in the middle of the message
+        for byte in &[SONG_POSITION, 0x02, TIMING_CLOCK, 0x01] {
+            clock.receive(&mut parser, *byte, now);
+        }
+        assert_eq!(clock.get_ticks(), 130 * PULSES_PER_MIDI_BEAT);
+        // interrupted by another status byte
+        for byte in &[SONG_POSITION, 0x02, 0x90, 0x01] {
+            clock.receive(&mut parser, *byte, now);
+        }
+        assert_eq!(clock.get_ticks(), 130 * PULSES_PER_MIDI_BEAT);
+    }
+
+    #[test]
+    fn estimate_tempo() {
+        let period = Duration::from_millis(20); // 125 bpm
+        let clock = MidiClock::from_reader(empty())
+            .with_period(Duration::from_secs(1))
+            .build();
+        let mut parser = Parser::default();
+        let mut now = Instant::now();
+        for _ in 0..200 {
+            clock.receive(&mut parser, TIMING_CLOCK, now);
+            now += period;
+        }
+        let error = clock.get_period().as_secs_f64() - period.as_secs_f64();
+        assert!(error.abs() < 1e-6);
+    }
+
+    #[test]
+    fn stop_tick() {
+        let (sender, receiver) = channel();
+        let x = Arc::new(AtomicUsize::new(0));
+        let y = x.clone();
+        let mut clock = MidiClock::from_reader(empty())
+            .on_tick(move |_| {
+                sender.send(()).unwrap();
+                sleep(Duration::from_millis(10));
+                let _ = y.fetch_add(1, SeqCst);
+            })
+            .build();
+        clock.start();
+        let reader = clock.clone();
+        let thread = spawn(move || {
+            let mut parser = Parser::default();
+            for byte in &[START, TIMING_CLOCK, TIMING_CLOCK] {
+                reader.receive(&mut parser, *byte, Instant::now());
+            }
+        });
+        receiver.recv().unwrap(); // first tick has begun
+        clock.stop();
+        // the tick finished before stop() returned, and no tick follows it
+        assert_eq!(x.load(SeqCst), 1);
+        thread.join().unwrap();
+        assert_eq!(x.load(SeqCst), 1);
+    }
+
+    #[test]
+    fn burst() {
+        let period = Duration::from_millis(20);
+        let clock = MidiClock::from_reader(empty()).with_period(period).build();
+        let mut parser = Parser::default();
+        let now = Instant::now();
+        // a backlog of clocks read at once keeps the estimate
+        for _ in 0..24 {
+            clock.receive(&mut parser, TIMING_CLOCK, now);
+        }
+        assert_eq!(clock.get_period(), period);
+    }
+
+    #[test]
+    fn from_stream() {
+        let mut stream = vec![TIMING_CLOCK, START];
+        stream.extend_from_slice(&[TIMING_CLOCK; 24]);
+        stream.push(STOP);
+        let (sender, receiver) = channel();
+        let mut clock = MidiClock::from_reader(Cursor::new(stream))
+            .on_tick(move |tick| sender.send(tick).unwrap())
+            .build();
+        clock.start();
+        let ticks: Vec<usize> = receiver.iter().take(24).collect();
+        assert_eq!(ticks, (1..=24).collect::<Vec<usize>>());
+    }
+}
@@ -0,0 +1,325 @@
+use super::{Clock, Curve, Period, Recorder, Stats, TickCallback};
+use std::fs::File;
+use std::io::Read;
+use std::os::raw::{c_int, c_long};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::rc::Rc;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst},
+    Arc,
+};
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+// minimal bindings for the Linux timerfd API, provided by libc
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct timespec {
+    tv_sec: c_long,
+    tv_nsec: c_long,
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct itimerspec {
+    it_interval: timespec,
+    it_value: timespec,
+}
+
+const CLOCK_MONOTONIC: c_int = 1;
+const TFD_CLOEXEC: c_int = 0o2000000;
+const TFD_TIMER_ABSTIME: c_int = 1;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+extern "C" {
+    fn clock_gettime(clockid: c_int, tp: *mut timespec) -> c_int;
+    fn timerfd_create(clockid: c_int, flags: c_int) -> c_int;
+    fn timerfd_settime(
+        fd: c_int,
+        flags: c_int,
+        new_value: *const itimerspec,
+        old_value: *mut itimerspec,
+    ) -> c_int;
+}
+
+// current CLOCK_MONOTONIC time in nanoseconds
+fn monotonic_now() -> u64 {
+    let mut now = timespec::default();
+    // only fails for an invalid clock id
+    unsafe { clock_gettime(CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * NANOS_PER_SEC + now.tv_nsec as u64
+}
+
+// arm a one shot timer. absolute deadlines are in CLOCK_MONOTONIC nanoseconds.
+fn arm(timer: &File, nanos: u64, flags: c_int) {
+    let value = itimerspec {
+        it_interval: timespec::default(), // zero interval is one shot
+        it_value: timespec {
+            tv_sec: (nanos / NANOS_PER_SEC) as c_long,
+            tv_nsec: (nanos % NANOS_PER_SEC) as c_long,
+        },
+    };
+    // only fails for an invalid fd or out of range value
+    unsafe { timerfd_settime(timer.as_raw_fd(), flags, &value, std::ptr::null_mut()) };
+}
+
+#[derive(Clone)]
+// a clock source that blocks on a kernel timer armed with absolute deadlines
+pub struct TimerFdClock {
+    callback: TickCallback,             // on tick callback
+    handle: Rc<Option<JoinHandle<()>>>, // worker thread wrapped in Rc for Clone
+    period: Period,                     // duration between clock ticks
+    running: Arc<AtomicBool>,           // clock state
+    stats: Recorder,                    // timing measurements
+    ticks: Arc<AtomicUsize>,            // number of ticks since clock start
+    timer: Option<Arc<File>>,           // timerfd shared with the worker thread
+}
+
+impl TimerFdClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn build(&self) -> Self {
+        Self {
+            callback: self.callback.clone(),
+            period: Period::new(self.period.get()),
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for TimerFdClock {
+    fn default() -> Self {
+        Self {
+            callback: Arc::new(|_| {}),
+            handle: Rc::new(Option::None),
+            period: Period::new(Duration::from_secs(1)),
+            running: Arc::new(AtomicBool::new(false)),
+            stats: Recorder::default(),
+            ticks: Arc::new(AtomicUsize::new(0)),
+            timer: None,
+        }
+    }
+}
+
+impl Clock for TimerFdClock {
+    fn start(&mut self) {
+        if self.running.load(SeqCst) {
+            return; // already running
+        }
+
+        let timer = self.timer.get_or_insert_with(|| {
+            let fd = unsafe { timerfd_create(CLOCK_MONOTONIC, TFD_CLOEXEC) };
+            assert!(fd >= 0, "could not create timerfd");
+            // File takes ownership of the fd and closes it on drop
+            Arc::new(unsafe { File::from_raw_fd(fd) })
+        });
+
+        // start running
+        self.running.store(true, SeqCst);
+
+        // variables to move into closure
+        let running = self.running.clone();
+        let ticks = self.ticks.clone();
+        let stats = self.stats.clone();
+        let shared_period = self.period.clone();
+        let callback = self.callback.clone();
+        let timer = timer.clone();
+
+        self.handle = Rc::new(Some(spawn(move || {
+            let mut deadline = monotonic_now();
+            let mut expirations = [0u8; 8];
+            // first tick occurs immediately, same as SystemClock
+            let called = Instant::now();
+            callback(ticks.fetch_add(1, SeqCst) + 1);
+            stats.tick(Duration::from_secs(0), called.elapsed());
+            shared_period.tick();
+            loop {
+                // Deadlines are absolute, so time spent in the callback or
+                // waking up does not accumulate into drift. The period is read
+                // on every tick so that changes take effect without a restart.
+                let period = shared_period.get().as_nanos() as u64;
+                // A deadline that already passed fires right away, so after
+                // a slow callback the missed ticks catch up back to back.
+                deadline += period;
+                arm(&timer, deadline, TFD_TIMER_ABSTIME);
+                // check after arming so a wake up from stop() is not lost
+                if !running.load(SeqCst) {
+                    break;
+                }
+                // blocks until the deadline passes
+                if (&*timer).read_exact(&mut expirations).is_err() || !running.load(SeqCst) {
+                    break;
+                }
+                let lateness = monotonic_now().saturating_sub(deadline);
+                if lateness >= period {
+                    stats.miss(1);
+                }
+                let called = Instant::now();
+                callback(ticks.fetch_add(1, SeqCst) + 1);
+                stats.tick(Duration::from_nanos(lateness), called.elapsed());
+                shared_period.tick();
+            }
+        })));
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, SeqCst);
+        if let Some(timer) = &self.timer {
+            // fire the timer right away to unblock the worker thread
+            arm(timer, 1, 0);
+        }
+        if let Some(reference) = Rc::get_mut(&mut self.handle) {
+            if let Some(handle) = reference.take() {
+                // consume handle and replace with None.
+                // only panics if worker thread panics.
+                // blocks here until clock is stopped.
+                handle.join().unwrap();
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.ticks.store(0, SeqCst);
+        self.stats.clear();
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(SeqCst)
+    }
+
+    fn with_period(&mut self, period: Duration) -> &mut Self {
+        self.period.set(period);
+        self
+    }
+
+    fn set_period(&mut self, period: Duration) {
+        self.period.set(period);
+    }
+
+    fn ramp_period(&mut self, period: Duration, ticks: usize, curve: Curve) {
+        self.period.ramp(period, ticks, curve);
+    }
+
+    fn on_tick<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.callback = Arc::new(callback);
+        self
+    }
+
+    fn get_ticks(&self) -> usize {
+        self.ticks.load(SeqCst)
+    }
+
+    fn get_period(&self) -> Duration {
+        self.period.get()
+    }
+
+    fn stats(&self) -> Stats {
+        self.stats.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::sync::Mutex;
+    use std::thread::sleep;
+    use std::time::Instant;
+
+    #[test]
+    fn count_ticks() {
+        let count = 10;
+        let period = Duration::from_millis(10);
+        let (sender, receiver) = channel();
+        let x = Arc::new(AtomicUsize::new(0));
+        let y = x.clone();
+        let mut clock = TimerFdClock::new()
+            .with_period(period)
+            .on_tick(move |tick| {
+                let _ = y.fetch_add(1, SeqCst);
+                sender.send(tick).unwrap();
+            })
+            .build();
+        let now = Instant::now();
+        clock.start();
+        for tick in 1..=count {
+            assert_eq!(receiver.recv_timeout(period * 100), Ok(tick as usize));
+        }
+        // the first tick is immediate, and no tick fires before it is due
+        assert!(now.elapsed() >= (count - 1) * period);
+        clock.stop();
+        assert!(clock.get_ticks() >= count as usize);
+        assert_eq!(clock.get_ticks(), x.load(SeqCst));
+    }
+
+    #[test]
+    fn stop_start() {
+        let period = Duration::from_secs(10);
+        let mut clock = TimerFdClock::new().with_period(period).build();
+        let now = Instant::now();
+        clock.start();
+        sleep(Duration::from_millis(10));
+        clock.stop(); // should not wait for the next deadline
+        assert!(now.elapsed() < period / 2);
+        let last_tick = clock.get_ticks();
+        clock.on_tick(move |tick| assert_eq!(last_tick + 1, tick));
+        clock.start();
+        sleep(Duration::from_millis(10));
+        clock.stop();
+        assert_eq!(last_tick + 1, clock.get_ticks());
+    }
+
+    #[test]
+    fn set_period() {
+        let period = Duration::from_millis(100);
+        let times = Arc::new(Mutex::new(Vec::new()));
+        let log = times.clone();
+        let mut clock = TimerFdClock::new()
+            .with_period(period)
+            .on_tick(move |_| log.lock().unwrap().push(Instant::now()))
+            .build();
+        clock.start();
+        sleep(period / 2);
+        clock.set_period(period / 10);
+        while clock.get_ticks() < 4 {
+            sleep(period / 10);
+        }
+        clock.stop();
+        assert_eq!(clock.get_period(), period / 10);
+        // the tick already armed fires on time, then the new period applies
+        let times = times.lock().unwrap();
+        assert!(times[1] - times[0] > times[3] - times[2]);
+    }
+
+    #[test]
+    fn stats() {
+        let period = Duration::from_millis(10);
+        let mut clock = TimerFdClock::new()
+            .with_period(period)
+            .on_tick(move |tick| {
+                if tick == 2 {
+                    sleep(period * 3); // miss a few deadlines
+                }
+            })
+            .build();
+        clock.start();
+        while clock.get_ticks() < 4 {
+            sleep(period);
+        }
+        clock.stop();
+        let stats = clock.stats();
+        assert!(stats.missed >= 1);
+        assert!(stats.max_callback >= period * 3);
+        assert!(stats.max_lateness >= period); // the tick after the stall
+        assert!(stats.mean_lateness <= stats.max_lateness);
+        clock.reset();
+        assert_eq!(clock.stats(), Stats::default());
+    }
+}
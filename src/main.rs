@@ -1,13 +1,18 @@
+#[cfg(all(target_os = "linux", feature = "timerfd"))]
+use sequencer::clock::TimerFdClock;
+#[cfg(not(all(target_os = "linux", feature = "timerfd")))]
+use sequencer::clock::{Mode, SystemClock};
 use sequencer::{
-    clock::SystemClock,
+    clock::{Clock, Curve, MidiClock},
     sequencer::Sequencer,
-    types::{u2, u4, u7, Controller, Event, Note, Param},
+    types::{u4, u7, Controller, Direction, Event, Length, Note, Nudge, Param, Rate, Swing, Tempo},
 };
 use std::convert::TryFrom;
 use std::env::args;
 use std::io::{self, Write};
 use std::num::NonZeroU8;
 use std::process::exit;
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
 
 fn main() {
     if args().len() < 2
@@ -15,17 +20,33 @@ fn main() {
         || args().nth(1).unwrap_or_else(|| "--help".to_string()) == "--help"
     {
         println!(
-            "A {} track {} step sequencer.",
-            Sequencer::TRACKS,
-            Sequencer::STEPS
+            "A step sequencer with up to {} steps and {} tracks.",
+            <Sequencer>::MAX_STEPS,
+            <Sequencer>::MAX_TRACKS
         );
         println!("\tRuns in real time. Accepts commands over stdin. Prints MIDI data over stdout.");
         println!();
         println!(
-            "Usage: {} <tempo> [--midiout]",
+            "Usage: {} <tempo> [--midiout] [--midiin <path>] [--tracks <count>] [--steps <count>]",
             args().next().unwrap_or_else(|| "sequencer".to_string())
         );
-        println!("\tfor <tempo> in beats per minute, use --midiout flag to write raw MIDI to stderr.");
+        println!(
+            "\tfor <tempo> in {}..{} beats per minute, use --midiout flag to write raw MIDI to stderr.",
+            Tempo::MIN,
+            Tempo::MAX
+        );
+        println!("\tRaw MIDI includes beat clock, start, stop, continue, and song position.");
+        println!("\tUse --midiin to follow MIDI beat clock read from a device, file, or pipe.");
+        println!(
+            "\tUse --tracks to play 1..{} tracks, one per MIDI channel. Defaults to {}.",
+            <Sequencer>::MAX_TRACKS,
+            <Sequencer>::DEFAULT_TRACKS
+        );
+        println!(
+            "\tUse --steps to set the pattern length to 1..{} steps. Defaults to {}.",
+            <Sequencer>::MAX_STEPS,
+            <Sequencer>::DEFAULT_STEPS
+        );
         println!();
         println!("Commands: Enter one of the following commands plus arguments during execution.");
         println!(
@@ -34,39 +55,111 @@ fn main() {
         println!(
             "\tpause                                                          (pause sequencer)"
         );
+        println!(
+            "\treset                                    (rewind paused sequencer to first step)"
+        );
         println!(
             "\tsteps                                            (print current and total steps)"
         );
+        println!(
+            "\tstats                                            (print clock timing statistics)"
+        );
+        println!(
+            "\tpanic [notes]                                       (silence all track channels)"
+        );
+        println!(
+            "\t\tsends all sound off and all notes off, plus a note off per pitch with [notes]"
+        );
+        println!(
+            "\ttempo <bpm>                                         (change tempo while running)"
+        );
+        println!(
+            "\ttap                                      (set tempo from average of recent taps)"
+        );
+        println!(
+            "\tramp <bpm> <steps> [curve]                              (gradually change tempo)"
+        );
+        println!(
+            "\t\tfor <steps> in 0..{} until <bpm> is reached, [curve] in lin/exp",
+            <Sequencer>::MAX_RAMP_STEPS
+        );
+        println!(
+            "\tswing <percent> [track]                                (delay every second step)"
+        );
+        println!(
+            "\t\tfor <percent> in {}..{}, sets swing of one [track] in 0..{} if given",
+            Swing::MIN,
+            Swing::MAX,
+            <Sequencer>::MAX_TRACKS - 1
+        );
+        println!(
+            "\tlength <track> <steps>                             (loop track over fewer steps)"
+        );
+        println!(
+            "\t\tfor <track> in 0..{}, <steps> in 1..{} up to the pattern length",
+            <Sequencer>::MAX_TRACKS - 1,
+            <Sequencer>::MAX_STEPS
+        );
+        println!(
+            "\trate <track> <rate>                                (play track faster or slower)"
+        );
+        println!(
+            "\t\tfor <track> in 0..{}, <rate> as <n>x or <n>/<d> for <n> and <d> in {}..{}",
+            <Sequencer>::MAX_TRACKS - 1,
+            Rate::MIN,
+            Rate::MAX
+        );
+        println!(
+            "\tdirection <track> <mode>                            (change order steps play in)"
+        );
+        println!(
+            "\t\tfor <track> in 0..{}, <mode> in fwd/rev/pingpong/pingpongrep/random/drunk",
+            <Sequencer>::MAX_TRACKS - 1
+        );
         println!(
             "\taddnote <track> <step> <pitch> <velocity> <duration>     (add note to sequencer)"
         );
         println!(
             "\t\tfor <track> in 0..{}, <step> in 0..{},",
-            Sequencer::TRACKS - 1,
-            Sequencer::STEPS - 1
+            <Sequencer>::MAX_TRACKS - 1,
+            <Sequencer>::MAX_STEPS - 1
         );
         println!(
             "\t\t<pitch> in 0..{}, <velocity> in 0..{}, <duration> in 0..{}",
             u7::MAX,
             u7::MAX,
-            u4::MAX
+            <Sequencer>::MAX_STEPS
         );
         println!(
             "\tdelnote <track> <step> <pitch>                        (remove note in sequencer)"
         );
         println!(
             "\t\tfor <track> in 0..{}, <step> in 0..{}, <pitch> in 0..{},",
-            Sequencer::TRACKS - 1,
-            Sequencer::STEPS - 1,
+            <Sequencer>::MAX_TRACKS - 1,
+            <Sequencer>::MAX_STEPS - 1,
+            u7::MAX
+        );
+        println!(
+            "\tnudge <track> <step> <pitch> <offset>                   (move note off the grid)"
+        );
+        println!(
+            "\t\tfor <track> in 0..{}, <step> in 0..{}, <pitch> in 0..{},",
+            <Sequencer>::MAX_TRACKS - 1,
+            <Sequencer>::MAX_STEPS - 1,
             u7::MAX
         );
+        println!(
+            "\t\t<offset> in {}..{} percent of a step, negative is early",
+            Nudge::MIN,
+            Nudge::MAX
+        );
         println!(
             "\taddparam <track> <step> <controller> <value>    (set parameter change for voice)"
         );
         println!(
             "\t\tfor <track> in 0..{}, <step> in 0..{},",
-            Sequencer::TRACKS - 1,
-            Sequencer::STEPS - 1
+            <Sequencer>::MAX_TRACKS - 1,
+            <Sequencer>::MAX_STEPS - 1
         );
         println!(
             "\t\t<controller> in mod/breath/vol/pan, <value> in 0..{}",
@@ -77,8 +170,8 @@ fn main() {
         );
         println!(
             "\t\tfor <track> in 0..{}, <step> in 0..{}, <controller> in mod/breath/vol/pan",
-            Sequencer::TRACKS - 1,
-            Sequencer::STEPS - 1,
+            <Sequencer>::MAX_TRACKS - 1,
+            <Sequencer>::MAX_STEPS - 1,
         );
 
         exit(0);
@@ -91,52 +184,109 @@ fn main() {
     });
 
     // parse midiout flag
-    let midiout = if let Some(flag) = args().nth(2) {
-        flag == "--midiout"
+    let midiout = args().skip(2).any(|arg| arg == "--midiout");
+
+    // parse tracks option
+    let tracks = parse_tracks(args().skip(2).skip_while(|arg| arg != "--tracks").nth(1))
+        .unwrap_or_else(|error| {
+            println!("Error: {}", error);
+            exit(-1);
+        });
+
+    // parse steps option
+    let steps = parse_steps(args().skip(2).skip_while(|arg| arg != "--steps").nth(1))
+        .unwrap_or_else(|error| {
+            println!("Error: {}", error);
+            exit(-1);
+        });
+
+    // parse midiin flag and open input, otherwise use an internal clock
+    let midiin = args().skip(2).skip_while(|arg| arg != "--midiin").nth(1);
+    if let Some(path) = midiin {
+        let clock = MidiClock::open(&path).unwrap_or_else(|error| {
+            println!("Error: could not open {}: {}", path, error);
+            exit(-1);
+        });
+        run(clock, tempo, tracks, steps, midiout);
     } else {
-        false
-    };
+        run(build_clock(), tempo, tracks, steps, midiout);
+    }
+}
 
-    // build sequencer
-    let mut sequencer = Sequencer::new()
+fn run<C: Clock + Clone>(
+    clock: C,
+    tempo: Tempo,
+    tracks: NonZeroU8,
+    steps: NonZeroU8,
+    midiout: bool,
+) {
+    // build sequencer, sending MIDI beat clock along with raw MIDI
+    let last_step = AtomicUsize::new(usize::MAX);
+    let length = usize::from(steps.get());
+    let mut sequencer = Sequencer::with_clock(clock)
         .with_tempo(tempo)
-        .on_step(move |step, events| {
-            print_step(step);
-            print_events(events, midiout);
+        .with_tracks(tracks)
+        .with_steps(steps)
+        .with_midi_clock(midiout)
+        .on_step_slice(move |step, events| {
+            // timing clocks arrive between steps. only redraw for a new step
+            // or for other events, so the events of the step stay on screen.
+            let redraw = last_step.swap(step, SeqCst) != step
+                || events.iter().any(|event| *event != Event::TimingClock);
+            if redraw {
+                print_step(step, length);
+            }
+            print_events(events, midiout, redraw);
         })
         .build();
 
     // read commands from stdin
     loop {
-        print_prompt();
+        print_prompt(sequencer.get_length());
         parse_command(&mut sequencer).unwrap_or_else(|error| println!("Error: {}", error));
     }
 }
 
-fn print_prompt() {
+// use a clock that sleeps between ticks to spare the CPU
+#[cfg(not(all(target_os = "linux", feature = "timerfd")))]
+fn build_clock() -> SystemClock {
+    SystemClock::new()
+        .with_mode(Mode::Hybrid(SystemClock::DEFAULT_SPIN))
+        .build()
+}
+
+// use kernel timers when available
+#[cfg(all(target_os = "linux", feature = "timerfd"))]
+fn build_clock() -> TimerFdClock {
+    TimerFdClock::new()
+}
+
+fn print_prompt(steps: usize) {
     print!("\x1b[0G");
-    print!("{:⬜<1$}", "", Sequencer::STEPS);
+    print!("{:⬜<1$}", "", steps);
     print!(" CMD: ");
     io::stdout().flush().unwrap();
 }
 
-fn print_step(step: usize) {
+fn print_step(step: usize, steps: usize) {
     print!("\x1b[s"); // save cursor location
     print!("\x1b[0G"); // goto beginning of line
     print!("{:⬜<1$}", "", step);
-    print!("⬛"); // print 15 white square and 1 black square for current step
-    print!("{:⬜<1$}", "", Sequencer::STEPS - step - 1);
+    print!("⬛"); // print white squares and 1 black square for current step
+    print!("{:⬜<1$}", "", steps - step - 1);
     print!("\x1b[u"); // goto saved position
     io::stdout().flush().unwrap();
 }
 
-fn print_events(events: Vec<Event>, midiout: bool) {
-    print!("\x1b[s"); // save cursor location
-    print!("\x1b[0K"); // erase to end of line
-    print!(" EVT: "); // print prompt
+fn print_events(events: &[Event], midiout: bool, redraw: bool) {
+    if redraw {
+        print!("\x1b[s"); // save cursor location
+        print!("\x1b[0K"); // erase to end of line
+        print!(" EVT: "); // print prompt
+    }
     let mut midi = [0u8; 3];
-    for event in events {
-        match event {
+    for event in events.iter().copied() {
+        let len = match event {
             Event::NoteOn {
                 channel,
                 pitch,
@@ -145,11 +295,13 @@ fn print_events(events: Vec<Event>, midiout: bool) {
                 midi[0] = 0x90 | u8::from(channel);
                 midi[1] = u8::from(pitch);
                 midi[2] = u8::from(velocity);
+                3
             }
             Event::NoteOff { channel, pitch } => {
                 midi[0] = 0x80 | u8::from(channel);
                 midi[1] = u8::from(pitch);
                 midi[2] = 0;
+                3
             }
             Event::ControllerChange {
                 channel,
@@ -159,25 +311,72 @@ fn print_events(events: Vec<Event>, midiout: bool) {
                 midi[0] = 0xB0 | u8::from(channel);
                 midi[1] = u8::from(controller);
                 midi[2] = u8::from(value);
+                3
             }
+            Event::AllSoundOff { channel } => {
+                midi[0] = 0xB0 | u8::from(channel);
+                midi[1] = 120;
+                midi[2] = 0;
+                3
+            }
+            Event::AllNotesOff { channel } => {
+                midi[0] = 0xB0 | u8::from(channel);
+                midi[1] = 123;
+                midi[2] = 0;
+                3
+            }
+            Event::TimingClock => {
+                midi[0] = 0xF8;
+                1
+            }
+            Event::Start => {
+                midi[0] = 0xFA;
+                1
+            }
+            Event::Continue => {
+                midi[0] = 0xFB;
+                1
+            }
+            Event::Stop => {
+                midi[0] = 0xFC;
+                1
+            }
+            Event::SongPosition { position } => {
+                let position = u16::from(position);
+                midi[0] = 0xF2;
+                midi[1] = (position & 0x7F) as u8; // lsb first
+                midi[2] = (position >> 7) as u8;
+                3
+            }
+            Event::Dropped => {
+                // not MIDI, only warn on screen
+                if redraw {
+                    print!("[WARNING: dropped step]");
+                }
+                continue;
+            }
+        };
+        if redraw {
+            print!("{:x?}", &midi[..len]);
         }
-        print!("{:x?}", midi);
         if midiout {
-            io::stderr().write_all(&midi).unwrap(); // write raw midi data to stderr
+            io::stderr().write_all(&midi[..len]).unwrap(); // write raw midi data to stderr
         }
     }
-    print!("\x1b[u"); // goto saved position
-    io::stdout().flush().unwrap();
+    if redraw {
+        print!("\x1b[u"); // goto saved position
+        io::stdout().flush().unwrap();
+    }
 }
 
-fn parse_command(sequencer: &mut Sequencer<SystemClock>) -> Result<(), &'static str> {
+fn parse_command<C: Clock>(sequencer: &mut Sequencer<C>) -> Result<(), &'static str> {
     let mut command = String::new();
 
     if io::stdin().read_line(&mut command).is_err() {
         return Err("could not read stdin");
     }
 
-    let mut args = command.trim().split_whitespace();
+    let mut args = command.split_whitespace();
     match args.next() {
         Some("start") => {
             sequencer.start();
@@ -187,6 +386,10 @@ fn parse_command(sequencer: &mut Sequencer<SystemClock>) -> Result<(), &'static
             sequencer.pause();
             Ok(())
         }
+        Some("reset") => {
+            sequencer.reset();
+            Ok(())
+        }
         Some("steps") => {
             let steps = sequencer.get_steps();
             println!(
@@ -196,11 +399,71 @@ fn parse_command(sequencer: &mut Sequencer<SystemClock>) -> Result<(), &'static
             );
             Ok(())
         }
-        Some("addnote") => parse_int::<u2>(args.next()).and_then(|track| {
-            parse_int::<u4>(args.next()).and_then(|step| {
+        Some("panic") => match args.next() {
+            None => {
+                sequencer.panic(false);
+                Ok(())
+            }
+            Some("notes") => {
+                sequencer.panic(true);
+                Ok(())
+            }
+            Some(_) => Err("invalid argument"),
+        },
+        Some("stats") => {
+            let stats = sequencer.get_stats();
+            println!(
+                "ticks: {} missed: {} dropped: {} lateness: {:?} mean: {:?} max: {:?} callback mean: {:?} max: {:?}",
+                stats.ticks,
+                stats.missed,
+                sequencer.get_dropped(),
+                stats.last_lateness,
+                stats.mean_lateness,
+                stats.max_lateness,
+                stats.mean_callback,
+                stats.max_callback
+            );
+            Ok(())
+        }
+        Some("tempo") => {
+            parse_tempo(args.next().map(String::from)).map(|tempo| sequencer.set_tempo(tempo))
+        }
+        Some("tap") => {
+            if let Some(tempo) = sequencer.tap() {
+                println!("tempo: {:.1}", f64::from(tempo));
+            }
+            Ok(())
+        }
+        Some("ramp") => parse_tempo(args.next().map(String::from)).and_then(|tempo| {
+            parse_ramp(args.next()).and_then(|steps| {
+                parse_curve(args.next()).map(|curve| sequencer.ramp_tempo(tempo, steps, curve))
+            })
+        }),
+        Some("swing") => parse_int::<Swing>(args.next()).and_then(|swing| {
+            if let Some(track) = args.next() {
+                parse_track(Some(track), sequencer.get_tracks())
+                    .map(|track| sequencer.set_track_swing(track, Some(swing)))
+            } else {
+                sequencer.set_swing(swing);
+                Ok(())
+            }
+        }),
+        Some("length") => parse_track(args.next(), sequencer.get_tracks()).and_then(|track| {
+            parse_length(args.next(), sequencer.get_length())
+                .map(|length| sequencer.set_track_length(track, Some(length)))
+        }),
+        Some("rate") => parse_track(args.next(), sequencer.get_tracks()).and_then(|track| {
+            parse_rate(args.next()).map(|rate| sequencer.set_track_rate(track, rate))
+        }),
+        Some("direction") => parse_track(args.next(), sequencer.get_tracks()).and_then(|track| {
+            parse_direction(args.next())
+                .map(|direction| sequencer.set_track_direction(track, direction))
+        }),
+        Some("addnote") => parse_track(args.next(), sequencer.get_tracks()).and_then(|track| {
+            parse_step(args.next(), sequencer.get_length()).and_then(|step| {
                 parse_int::<u7>(args.next()).and_then(|pitch| {
                     parse_int::<u7>(args.next()).and_then(|velocity| {
-                        parse_int::<u4>(args.next()).map(|duration| {
+                        parse_duration(args.next(), sequencer.get_length()).map(|duration| {
                             sequencer.add_note(
                                 track,
                                 step,
@@ -208,6 +471,7 @@ fn parse_command(sequencer: &mut Sequencer<SystemClock>) -> Result<(), &'static
                                     pitch,
                                     velocity,
                                     duration,
+                                    offset: Nudge::ZERO,
                                 },
                             )
                         })
@@ -215,22 +479,35 @@ fn parse_command(sequencer: &mut Sequencer<SystemClock>) -> Result<(), &'static
                 })
             })
         }),
-        Some("delnote") => parse_int::<u2>(args.next()).and_then(|track| {
-            parse_int::<u4>(args.next()).and_then(|step| {
+        Some("delnote") => parse_track(args.next(), sequencer.get_tracks()).and_then(|track| {
+            parse_step(args.next(), sequencer.get_length()).and_then(|step| {
                 parse_int::<u7>(args.next())
                     .map(|pitch| sequencer.delete_note(track, step, Note::from_pitch(pitch)))
             })
         }),
-        Some("addparam") => parse_int::<u2>(args.next()).and_then(|track| {
-            parse_int::<u4>(args.next()).and_then(|step| {
+        Some("nudge") => parse_track(args.next(), sequencer.get_tracks()).and_then(|track| {
+            parse_step(args.next(), sequencer.get_length()).and_then(|step| {
+                parse_int::<u7>(args.next()).and_then(|pitch| {
+                    parse_nudge(args.next()).map(|offset| {
+                        let note = Note {
+                            offset,
+                            ..Note::from_pitch(pitch)
+                        };
+                        sequencer.nudge_note(track, step, note)
+                    })
+                })
+            })
+        }),
+        Some("addparam") => parse_track(args.next(), sequencer.get_tracks()).and_then(|track| {
+            parse_step(args.next(), sequencer.get_length()).and_then(|step| {
                 parse_controller(args.next()).and_then(|controller| {
                     parse_int::<u7>(args.next())
                         .map(|value| sequencer.set_param(track, step, Param { controller, value }))
                 })
             })
         }),
-        Some("delparam") => parse_int::<u2>(args.next()).and_then(|track| {
-            parse_int::<u4>(args.next()).and_then(|step| {
+        Some("delparam") => parse_track(args.next(), sequencer.get_tracks()).and_then(|track| {
+            parse_step(args.next(), sequencer.get_length()).and_then(|step| {
                 parse_controller(args.next()).map(|controller| {
                     sequencer.clear_param(track, step, Param::from_controller(controller))
                 })
@@ -240,13 +517,13 @@ fn parse_command(sequencer: &mut Sequencer<SystemClock>) -> Result<(), &'static
     }
 }
 
-fn parse_tempo(arg: Option<String>) -> Result<NonZeroU8, &'static str> {
+fn parse_tempo(arg: Option<String>) -> Result<Tempo, &'static str> {
     if let Some(string) = arg {
-        if let Ok(int) = string.parse::<u8>() {
-            if let Some(tempo) = NonZeroU8::new(int) {
+        if let Ok(float) = string.parse::<f64>() {
+            if let Ok(tempo) = Tempo::try_from(float) {
                 Ok(tempo)
             } else {
-                Err("tempo cannot be zero")
+                Err("tempo is out of bounds")
             }
         } else {
             Err("could not parse tempo")
@@ -256,6 +533,38 @@ fn parse_tempo(arg: Option<String>) -> Result<NonZeroU8, &'static str> {
     }
 }
 
+fn parse_count(arg: Option<&str>) -> Result<usize, &'static str> {
+    if let Some(string) = arg {
+        if let Ok(count) = string.parse::<usize>() {
+            Ok(count)
+        } else {
+            Err("could not parse arg")
+        }
+    } else {
+        Err("missing argument")
+    }
+}
+
+// ramp can be up to the longest ramp the sequencer accepts
+fn parse_ramp(arg: Option<&str>) -> Result<usize, &'static str> {
+    parse_count(arg).and_then(|steps| {
+        if steps <= <Sequencer>::MAX_RAMP_STEPS {
+            Ok(steps)
+        } else {
+            Err("ramp is too long")
+        }
+    })
+}
+
+// curve is optional and defaults to linear
+fn parse_curve(arg: Option<&str>) -> Result<Curve, &'static str> {
+    match arg {
+        None | Some("lin") => Ok(Curve::Linear),
+        Some("exp") => Ok(Curve::Exponential),
+        _ => Err("invalid curve"),
+    }
+}
+
 fn parse_int<T: TryFrom<u8>>(arg: Option<&str>) -> Result<T, &'static str> {
     if let Some(string) = arg {
         if let Ok(int) = string.parse::<u8>() {
@@ -272,6 +581,116 @@ fn parse_int<T: TryFrom<u8>>(arg: Option<&str>) -> Result<T, &'static str> {
     }
 }
 
+// tracks are optional and default to the sequencer default
+fn parse_tracks(arg: Option<String>) -> Result<NonZeroU8, &'static str> {
+    match arg {
+        None => Ok(NonZeroU8::new(<Sequencer>::DEFAULT_TRACKS as u8).unwrap()),
+        Some(string) => match parse_int::<NonZeroU8>(Some(&string)) {
+            Ok(tracks) if usize::from(tracks.get()) <= <Sequencer>::MAX_TRACKS => Ok(tracks),
+            Ok(_) | Err("number is out of bounds") => Err("track count is out of bounds"),
+            Err(error) => Err(error),
+        },
+    }
+}
+
+// steps are optional and default to the sequencer default
+fn parse_steps(arg: Option<String>) -> Result<NonZeroU8, &'static str> {
+    match arg {
+        None => Ok(NonZeroU8::new(<Sequencer>::DEFAULT_STEPS as u8).unwrap()),
+        Some(string) => match parse_int::<NonZeroU8>(Some(&string)) {
+            Ok(steps) if usize::from(steps.get()) <= <Sequencer>::MAX_STEPS => Ok(steps),
+            Ok(_) | Err("number is out of bounds") => Err("step count is out of bounds"),
+            Err(error) => Err(error),
+        },
+    }
+}
+
+// step must be within the pattern
+fn parse_step(arg: Option<&str>, steps: usize) -> Result<u7, &'static str> {
+    parse_int::<u7>(arg).and_then(|step| {
+        if usize::from(step) < steps {
+            Ok(step)
+        } else {
+            Err("step does not exist")
+        }
+    })
+}
+
+// track length can be up to the pattern length
+fn parse_length(arg: Option<&str>, steps: usize) -> Result<NonZeroU8, &'static str> {
+    parse_int::<NonZeroU8>(arg).and_then(|length| {
+        if usize::from(length.get()) <= steps {
+            Ok(length)
+        } else {
+            Err("length is longer than pattern")
+        }
+    })
+}
+
+// duration can be up to the pattern length
+fn parse_duration(arg: Option<&str>, steps: usize) -> Result<Length, &'static str> {
+    parse_int::<Length>(arg).and_then(|duration| {
+        if usize::from(duration) <= steps {
+            Ok(duration)
+        } else {
+            Err("duration is longer than pattern")
+        }
+    })
+}
+
+// track must exist in the sequencer
+fn parse_track(arg: Option<&str>, tracks: usize) -> Result<u4, &'static str> {
+    parse_int::<u4>(arg).and_then(|track| {
+        if usize::from(track) < tracks {
+            Ok(track)
+        } else {
+            Err("track does not exist")
+        }
+    })
+}
+
+// rate is a multiplier like 2x, or a multiplier and divider like 1/2
+fn parse_rate(arg: Option<&str>) -> Result<Rate, &'static str> {
+    if let Some(string) = arg {
+        let ratio = if let Some(multiplier) = string.strip_suffix('x') {
+            multiplier.parse::<u8>().map(|multiplier| (multiplier, 1))
+        } else if let Some((multiplier, divider)) = string.split_once('/') {
+            multiplier
+                .parse::<u8>()
+                .and_then(|multiplier| divider.parse::<u8>().map(|divider| (multiplier, divider)))
+        } else {
+            string.parse::<u8>().map(|multiplier| (multiplier, 1))
+        };
+        if let Ok(ratio) = ratio {
+            if let Ok(rate) = Rate::try_from(ratio) {
+                Ok(rate)
+            } else {
+                Err("rate is out of bounds")
+            }
+        } else {
+            Err("could not parse rate")
+        }
+    } else {
+        Err("missing argument")
+    }
+}
+
+fn parse_nudge(arg: Option<&str>) -> Result<Nudge, &'static str> {
+    if let Some(string) = arg {
+        if let Ok(int) = string.parse::<i8>() {
+            if let Ok(offset) = Nudge::try_from(int) {
+                Ok(offset)
+            } else {
+                Err("offset is out of bounds")
+            }
+        } else {
+            Err("could not parse offset")
+        }
+    } else {
+        Err("missing argument")
+    }
+}
+
 fn parse_controller(arg: Option<&str>) -> Result<Controller, &'static str> {
     if let Some(string) = arg {
         match string {
@@ -286,19 +705,41 @@ fn parse_controller(arg: Option<&str>) -> Result<Controller, &'static str> {
     }
 }
 
+fn parse_direction(arg: Option<&str>) -> Result<Direction, &'static str> {
+    if let Some(string) = arg {
+        match string {
+            "fwd" => Ok(Direction::Forward),
+            "rev" => Ok(Direction::Reverse),
+            "pingpong" => Ok(Direction::PingPong),
+            "pingpongrep" => Ok(Direction::PingPongRepeat),
+            "random" => Ok(Direction::Random),
+            "drunk" => Ok(Direction::Drunk),
+            _ => Err("invalid direction"),
+        }
+    } else {
+        Err("missing argument")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn good_tempo() {
-        for i in 1..u8::MAX {
+        for i in 1..Tempo::MAX as u16 {
             let string = format!("{}", i);
             let tempo = parse_tempo(Some(string)).unwrap();
-            assert_eq!(u8::from(tempo), i);
+            assert_eq!(f64::from(tempo), i as f64);
         }
     }
 
+    #[test]
+    fn fractional_tempo() {
+        let tempo = parse_tempo(Some("87.5".to_string())).unwrap();
+        assert_eq!(f64::from(tempo), 87.5);
+    }
+
     #[test]
     #[should_panic]
     fn none_tempo() {
@@ -312,12 +753,141 @@ mod tests {
     }
 
     #[test]
-    fn good_u2() {
-        for i in 0..u2::MAX {
-            let string = format!("{}", i);
-            let int = parse_int::<u2>(Some(&string)).unwrap();
-            assert_eq!(u8::from(int), i);
-        }
+    #[should_panic]
+    fn bad_tempo() {
+        parse_tempo(Some("1000".to_string())).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn nan_tempo() {
+        parse_tempo(Some("NaN".to_string())).unwrap();
+    }
+
+    #[test]
+    fn good_curve() {
+        assert_eq!(Ok(Curve::Linear), parse_curve(None));
+        assert_eq!(Ok(Curve::Linear), parse_curve(Some("lin")));
+        assert_eq!(Ok(Curve::Exponential), parse_curve(Some("exp")));
+    }
+
+    #[test]
+    #[should_panic]
+    fn bad_curve() {
+        parse_curve(Some("log")).unwrap();
+    }
+
+    #[test]
+    fn good_count() {
+        assert_eq!(Ok(256), parse_count(Some("256")));
+    }
+
+    #[test]
+    #[should_panic]
+    fn none_count() {
+        parse_count(None).unwrap();
+    }
+
+    #[test]
+    fn bad_ramp() {
+        assert_eq!(Ok(65535), parse_ramp(Some("65535")));
+        assert_eq!(Err("ramp is too long"), parse_ramp(Some("65536")));
+        assert_eq!(
+            Err("ramp is too long"),
+            parse_ramp(Some("18446744073709551615"))
+        );
+    }
+
+    #[test]
+    fn good_tracks() {
+        assert_eq!(Ok(4), parse_tracks(None).map(NonZeroU8::get));
+        assert_eq!(
+            Ok(16),
+            parse_tracks(Some("16".to_string())).map(NonZeroU8::get)
+        );
+        assert_eq!(
+            Err("track count is out of bounds"),
+            parse_tracks(Some("17".to_string()))
+        );
+        assert_eq!(
+            Err("track count is out of bounds"),
+            parse_tracks(Some("0".to_string()))
+        );
+    }
+
+    #[test]
+    fn good_track() {
+        assert_eq!(Ok(15), parse_track(Some("15"), 16).map(u8::from));
+        assert_eq!(Err("track does not exist"), parse_track(Some("4"), 4));
+        assert_eq!(Err("number is out of bounds"), parse_track(Some("16"), 16));
+        assert_eq!(Err("missing argument"), parse_track(None, 16));
+    }
+
+    #[test]
+    fn good_steps() {
+        assert_eq!(Ok(16), parse_steps(None).map(NonZeroU8::get));
+        assert_eq!(
+            Ok(128),
+            parse_steps(Some("128".to_string())).map(NonZeroU8::get)
+        );
+        assert_eq!(
+            Err("step count is out of bounds"),
+            parse_steps(Some("129".to_string()))
+        );
+        assert_eq!(
+            Err("step count is out of bounds"),
+            parse_steps(Some("0".to_string()))
+        );
+    }
+
+    #[test]
+    fn good_step() {
+        assert_eq!(Ok(127), parse_step(Some("127"), 128).map(u8::from));
+        assert_eq!(Err("step does not exist"), parse_step(Some("7"), 7));
+        assert_eq!(Err("number is out of bounds"), parse_step(Some("128"), 128));
+        assert_eq!(Err("missing argument"), parse_step(None, 16));
+    }
+
+    #[test]
+    fn good_length() {
+        assert_eq!(Ok(5), parse_length(Some("5"), 16).map(NonZeroU8::get));
+        assert_eq!(Ok(16), parse_length(Some("16"), 16).map(NonZeroU8::get));
+        assert_eq!(
+            Err("length is longer than pattern"),
+            parse_length(Some("17"), 16)
+        );
+        assert_eq!(Err("number is out of bounds"), parse_length(Some("0"), 16));
+    }
+
+    #[test]
+    fn good_rate() {
+        assert_eq!(Ok(Rate::NORMAL), parse_rate(Some("1")));
+        assert_eq!(Rate::try_from((2, 1)), Ok(parse_rate(Some("2x")).unwrap()));
+        assert_eq!(Rate::try_from((1, 2)), Ok(parse_rate(Some("1/2")).unwrap()));
+        assert_eq!(Rate::try_from((3, 2)), Ok(parse_rate(Some("3/2")).unwrap()));
+        assert_eq!(Err("rate is out of bounds"), parse_rate(Some("1/17")));
+        assert_eq!(Err("rate is out of bounds"), parse_rate(Some("0x")));
+        assert_eq!(Err("could not parse rate"), parse_rate(Some("half")));
+        assert_eq!(Err("missing argument"), parse_rate(None));
+    }
+
+    #[test]
+    fn good_duration() {
+        assert_eq!(Ok(0), parse_duration(Some("0"), 16).map(u8::from));
+        assert_eq!(Ok(128), parse_duration(Some("128"), 128).map(u8::from));
+        assert_eq!(
+            Err("duration is longer than pattern"),
+            parse_duration(Some("17"), 16)
+        );
+    }
+
+    #[test]
+    fn bad_duration() {
+        assert!(Length::try_from(129_u8).is_err());
+        assert_eq!(
+            Err("number is out of bounds"),
+            parse_duration(Some("129"), 128)
+        );
     }
 
     #[test]
@@ -338,12 +908,6 @@ mod tests {
         }
     }
 
-    #[test]
-    #[should_panic]
-    fn none_u2() {
-        parse_int::<u2>(None).unwrap();
-    }
-
     #[test]
     #[should_panic]
     fn none_u4() {
@@ -356,12 +920,6 @@ mod tests {
         parse_int::<u7>(None).unwrap();
     }
 
-    #[test]
-    #[should_panic]
-    fn bad_u2() {
-        parse_int::<u2>(Some("4")).unwrap();
-    }
-
     #[test]
     #[should_panic]
     fn bad_u4() {
@@ -374,6 +932,27 @@ mod tests {
         parse_int::<u7>(Some("128")).unwrap();
     }
 
+    #[test]
+    fn good_swing() {
+        assert_eq!(Ok(Swing::STRAIGHT), parse_int::<Swing>(Some("50")));
+        assert_eq!(66, u8::from(parse_int::<Swing>(Some("66")).unwrap()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn bad_swing() {
+        parse_int::<Swing>(Some("76")).unwrap();
+    }
+
+    #[test]
+    fn good_nudge() {
+        assert_eq!(Ok(Nudge::ZERO), parse_nudge(Some("0")));
+        assert_eq!(-25, i8::from(parse_nudge(Some("-25")).unwrap()));
+        assert_eq!(Err("offset is out of bounds"), parse_nudge(Some("51")));
+        assert_eq!(Err("could not parse offset"), parse_nudge(Some("early")));
+        assert_eq!(Err("missing argument"), parse_nudge(None));
+    }
+
     #[test]
     fn good_controller() {
         assert_eq!(Ok(Controller::Modulation), parse_controller(Some("mod")));
@@ -393,4 +972,19 @@ mod tests {
     fn bad_controller() {
         parse_controller(Some("modulation")).unwrap();
     }
+
+    #[test]
+    fn good_direction() {
+        assert_eq!(Ok(Direction::Forward), parse_direction(Some("fwd")));
+        assert_eq!(Ok(Direction::Reverse), parse_direction(Some("rev")));
+        assert_eq!(Ok(Direction::PingPong), parse_direction(Some("pingpong")));
+        assert_eq!(
+            Ok(Direction::PingPongRepeat),
+            parse_direction(Some("pingpongrep"))
+        );
+        assert_eq!(Ok(Direction::Random), parse_direction(Some("random")));
+        assert_eq!(Ok(Direction::Drunk), parse_direction(Some("drunk")));
+        assert_eq!(Err("invalid direction"), parse_direction(Some("up")));
+        assert_eq!(Err("missing argument"), parse_direction(None));
+    }
 }
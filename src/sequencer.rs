@@ -1,9 +1,18 @@
-use crate::clock::{Clock, SystemClock};
-use crate::types::{u2, u4, u7, Controller, Event, Note, Param};
+mod queue;
+
+use self::queue::{queue, Consumer, Producer};
+use crate::clock::{Clock, Curve, Stats, SystemClock};
+use crate::types::{
+    u14, u4, u7, Controller, Direction, Event, Note, Nudge, Param, Rate, Swing, Tempo,
+};
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::convert::TryFrom;
-use std::num::NonZeroU8;
-use std::sync::{Arc, Mutex};
+use std::num::{NonZeroU16, NonZeroU8};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering::SeqCst},
+    Arc, Mutex,
+};
 use std::time::{Duration, Instant};
 
 #[derive(Default, Debug)]
@@ -27,257 +36,1504 @@ impl Voice {
 
 #[derive(Debug)]
 struct Step {
-    note_ons: Vec<Note>, // pitch, velocity, and duration
-    note_offs: Vec<u7>,  // just pitch
+    note_ons: Vec<Note>, // pitch, velocity, duration, and offset
     params: Vec<Param>,  // controller and value
 }
 
 impl Default for Step {
+    // pitches and controllers are unique within a step, so reserving room for
+    // all of them means edits on the clock thread never reallocate
     fn default() -> Self {
         Self {
-            note_ons: Vec::new(),
-            note_offs: Vec::new(),
-            params: Vec::new(),
+            note_ons: Vec::with_capacity(u7::MAX as usize + 1),
+            params: Vec::with_capacity(Controller::COUNT),
         }
     }
 }
 
-#[derive(Default, Debug)]
+// xorshift random number generator
+#[derive(Debug)]
+struct XorShift(u64);
+
+impl XorShift {
+    // spread small seeds over all bits, and never start at zero
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// a random number for a step of a track. the same step always gets the same
+// number, however often it is looked at.
+fn random(track: usize, step: usize) -> usize {
+    let mut rng = XorShift::new((track as u64) << 32 ^ step as u64);
+    (rng.next() >> 32) as usize
+}
+
+// recent positions of a random walk, by step number
+#[derive(Debug)]
+struct Walk {
+    last: Option<usize>,            // latest step walked to
+    positions: [u8; Walk::HISTORY], // position of each recent step
+}
+
+impl Walk {
+    const HISTORY: usize = 64; // more than the steps looked at in one tick
+
+    fn new() -> Self {
+        Self {
+            last: None,
+            positions: [0; Self::HISTORY],
+        }
+    }
+
+    // walk one position forward or back for each step since the last one.
+    // start over from the first position after a jump.
+    fn position(&mut self, track: usize, step: usize, length: usize) -> usize {
+        let last = match self.last {
+            Some(last) if step <= last && last - step < Self::HISTORY => {
+                return usize::from(self.positions[step % Self::HISTORY]) % length;
+            }
+            Some(last) if step > last && step - last < Self::HISTORY => last,
+            _ => {
+                self.positions[step % Self::HISTORY] = 0;
+                step
+            }
+        };
+        for n in last + 1..=step {
+            let position = usize::from(self.positions[(n - 1) % Self::HISTORY]) % length;
+            let position = if random(track, n) % 2 == 1 {
+                (position + length - 1) % length
+            } else {
+                (position + 1) % length
+            };
+            self.positions[n % Self::HISTORY] = position as u8;
+        }
+        self.last = Some(step);
+        usize::from(self.positions[step % Self::HISTORY]) % length
+    }
+}
+
+#[derive(Debug)]
 struct Track {
     voice: Voice,
-    steps: [Step; Sequencer::STEPS],
+    swing: Option<Swing>,  // overrides the sequencer swing
+    length: Option<usize>, // overrides the pattern length
+    rate: Rate,            // steps per sequencer step
+    direction: Direction,  // order steps are played in
+    walk: Walk,            // positions of the Drunk direction
+    note_offs: [Option<usize>; u7::MAX as usize + 1], // clock tick each sounding pitch is due off
+    steps: Vec<Step>,      // one per step of the pattern
+}
+
+impl Track {
+    fn new(steps: usize) -> Self {
+        Self {
+            voice: Voice::default(),
+            swing: None,
+            length: None,
+            rate: Rate::NORMAL,
+            direction: Direction::Forward,
+            walk: Walk::new(),
+            note_offs: [None; u7::MAX as usize + 1],
+            steps: (0..steps).map(|_| Step::default()).collect(),
+        }
+    }
+
+    // position in the pattern of the nth step played by this track
+    fn position(&mut self, track: usize, step: usize, length: usize) -> usize {
+        match self.direction {
+            Direction::Forward => step % length,
+            Direction::Reverse => length - 1 - step % length,
+            Direction::PingPong if length == 1 => 0,
+            Direction::PingPong => {
+                let turn = step % (2 * length - 2);
+                turn.min(2 * length - 2 - turn)
+            }
+            Direction::PingPongRepeat => {
+                let turn = step % (2 * length);
+                turn.min(2 * length - 1 - turn)
+            }
+            Direction::Random => random(track, step) % length,
+            Direction::Drunk => self.walk.position(track, step, length),
+        }
+    }
+}
+
+// shared on step callback
+type StepCallback = Arc<dyn Fn(usize, &[Event]) + Send + Sync>;
+
+#[derive(Clone, Copy, Debug)]
+// a change to step data, queued for the clock thread
+enum Edit {
+    AddNote(u4, u7, Note),
+    NudgeNote(u4, u7, Note),
+    DeleteNote(u4, u7, Note),
+    SetParam(u4, u7, Param),
+    ClearParam(u4, u7, Param),
+    TrackSwing(u4, Option<Swing>),
+    TrackLength(u4, Option<NonZeroU8>),
+    TrackRate(u4, Rate),
+    TrackDirection(u4, Direction),
+    Panic(bool), // silence every channel, with a note off per pitch if set
+}
+
+// step data and the edits waiting to be applied to it. only the clock thread
+// locks this while running, so the lock is never contended on a tick.
+struct Engine {
+    edits: Consumer<Edit>,
+    pulse: Option<usize>, // last tick played
+    tracks: Vec<Track>,   // one per MIDI channel
+}
+
+impl Engine {
+    // apply all queued edits in order. some edits also generate events.
+    fn apply_edits(&mut self, events: &mut Vec<Event>) {
+        while let Some(edit) = self.edits.pop() {
+            self.apply(edit, events);
+        }
+    }
+
+    // set the number of tracks and the number of steps in each track. removed
+    // tracks and steps are dropped with their data. track lengths are cut to
+    // fit and random walks start over.
+    fn resize(&mut self, tracks: usize, steps: usize) {
+        self.tracks.resize_with(tracks, || Track::new(steps));
+        for track in self.tracks.iter_mut() {
+            track.steps.resize_with(steps, Step::default);
+            track.length = track.length.map(|length| length.min(steps));
+            track.walk = Walk::new();
+        }
+    }
+
+    // the clock jumped back from one tick to an earlier one, for example when
+    // a MIDI master starts over. keep the ticks each sounding note has left.
+    fn rewind(&mut self, from: usize, to: usize) {
+        for track in self.tracks.iter_mut() {
+            for due in track.note_offs.iter_mut().flatten() {
+                *due = to + due.saturating_sub(from + 1);
+            }
+        }
+    }
+
+    // end every sounding note now instead of when it is due
+    fn note_offs(&mut self, events: &mut Vec<Event>) {
+        for (i, track) in self.tracks.iter_mut().enumerate() {
+            let channel = u4::try_from(i as u8).unwrap();
+            for (pitch, due) in track.note_offs.iter_mut().enumerate() {
+                if due.take().is_some() {
+                    let pitch = u7::try_from(pitch).unwrap();
+                    events.push(Event::NoteOff { channel, pitch });
+                }
+            }
+        }
+    }
+
+    // edits for tracks or steps that do not exist are ignored
+    fn apply(&mut self, edit: Edit, events: &mut Vec<Event>) {
+        match edit {
+            Edit::AddNote(track, step, note) => {
+                if let Some(step) = self.step(track, step) {
+                    step.note_ons.retain(|n| n.pitch != note.pitch);
+                    step.note_ons.push(note);
+                }
+            }
+            Edit::NudgeNote(track, step, note) => {
+                if let Some(step) = self.step(track, step) {
+                    for n in step.note_ons.iter_mut().filter(|n| n.pitch == note.pitch) {
+                        n.offset = note.offset;
+                    }
+                }
+            }
+            Edit::DeleteNote(track, step, note) => {
+                if let Some(step) = self.step(track, step) {
+                    step.note_ons.retain(|n| n.pitch != note.pitch);
+                }
+            }
+            Edit::SetParam(track, step, param) => {
+                if let Some(step) = self.step(track, step) {
+                    step.params.retain(|p| p.controller != param.controller);
+                    step.params.push(param);
+                }
+            }
+            Edit::ClearParam(track, step, param) => {
+                if let Some(step) = self.step(track, step) {
+                    step.params.retain(|p| p.controller != param.controller);
+                }
+            }
+            Edit::TrackSwing(track, swing) => {
+                if let Some(track) = self.tracks.get_mut(usize::from(track)) {
+                    track.swing = swing;
+                }
+            }
+            Edit::TrackLength(track, length) => {
+                if let Some(track) = self.tracks.get_mut(usize::from(track)) {
+                    let steps = track.steps.len();
+                    track.length = length.map(|length| usize::from(length.get()).min(steps));
+                }
+            }
+            Edit::TrackRate(track, rate) => {
+                if let Some(track) = self.tracks.get_mut(usize::from(track)) {
+                    track.rate = rate;
+                }
+            }
+            Edit::TrackDirection(track, direction) => {
+                if let Some(track) = self.tracks.get_mut(usize::from(track)) {
+                    track.direction = direction;
+                }
+            }
+            Edit::Panic(note_offs) => {
+                // the notes waiting to end are silenced here, so forget them
+                for (i, track) in self.tracks.iter_mut().enumerate() {
+                    let channel = u4::try_from(i as u8).unwrap();
+                    track.note_offs = [None; u7::MAX as usize + 1];
+                    events.push(Event::AllSoundOff { channel });
+                    events.push(Event::AllNotesOff { channel });
+                    if note_offs {
+                        for pitch in 0..=u7::MAX {
+                            let pitch = u7::try_from(pitch).unwrap();
+                            events.push(Event::NoteOff { channel, pitch });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn step(&mut self, track: u4, step: u7) -> Option<&mut Step> {
+        let track = self.tracks.get_mut(usize::from(track))?;
+        track.steps.get_mut(usize::from(step))
+    }
 }
 
-// a 4 track, 16 step sequencer
-pub struct Sequencer<Clock> {
-    clock: RefCell<Clock>, // implements the Clock trait
-    callback: Arc<dyn Fn(usize, Vec<Event>) + Send + Sync>, // on step event
-    tracks: Arc<Mutex<[Track; Sequencer::TRACKS]>>, // step data
+// a sequencer with up to 128 steps and 16 tracks driven by any Clock implementation
+pub struct Sequencer<C = SystemClock> {
+    clock: RefCell<C>,          // implements the Clock trait
+    dropped: Arc<AtomicUsize>,  // ticks lost to lock contention
+    callback: StepCallback,     // on step event
+    midi_clock: bool,           // emit MIDI beat clock and transport events
+    ppqn: u16,                  // clock ticks per quarter note
+    skipped: usize,             // ticks skipped to resume on a step
+    swing: Arc<AtomicU8>,       // swing percent for all tracks
+    taps: VecDeque<Instant>,    // recent tap tempo times
+    steps: usize,               // pattern length in steps
+    tracks: usize,              // number of tracks
+    edits: Producer<Edit>,      // changes to step data for the clock thread
+    engine: Arc<Mutex<Engine>>, // step data
 }
 
-impl Sequencer<SystemClock> {
+impl<C: Clock> Sequencer<C> {
     pub const STEPS_PER_BEAT: u8 = 4;
-    pub const STEPS: usize = u4::MAX as usize + 1;
-    pub const TRACKS: usize = u2::MAX as usize + 1;
+    pub const DEFAULT_STEPS: usize = 16;
+    pub const MAX_STEPS: usize = u7::MAX as usize + 1;
+    pub const DEFAULT_TRACKS: usize = 4;
+    pub const MAX_TRACKS: usize = u4::MAX as usize + 1; // one per MIDI channel
+    pub const DEFAULT_PPQN: u16 = 24; // same resolution as MIDI beat clock
+    pub const MIDI_CLOCK_PPQN: usize = 24;
+    pub const TAPS: usize = 4; // tap intervals averaged for tap tempo
+    pub const MAX_RAMP_STEPS: usize = u16::MAX as usize; // longest ramp the command line accepts
+    pub const TAP_TIMEOUT: Duration = Duration::from_secs(2); // start over after a pause
+    pub const EDITS: usize = 256; // edits that can wait for the next tick
+    pub const EVENTS: usize = 256; // events per tick before the buffer grows
 
-    pub fn new() -> Self {
+    pub fn new() -> Self
+    where
+        C: Default,
+    {
         Self::default()
     }
 
-    pub fn with_tempo(&mut self, bpm: NonZeroU8) -> &mut Self {
-        // beats per min to steps per min to period in seconds
-        let period = 60.0 / bpm.get() as f32 / Self::STEPS_PER_BEAT as f32;
-        self.clock
-            .get_mut()
-            .with_period(Duration::from_secs_f32(period));
+    // create a sequencer driven by the provided clock instance
+    pub fn with_clock(clock: C) -> Self {
+        let (edits, consumer) = queue(Self::EDITS);
+        Self {
+            clock: RefCell::new(clock),
+            dropped: Arc::new(AtomicUsize::new(0)),
+            callback: Arc::new(|_, _| {}),
+            midi_clock: false,
+            ppqn: Self::DEFAULT_PPQN,
+            skipped: 0,
+            swing: Arc::new(AtomicU8::new(Swing::STRAIGHT.into())),
+            taps: VecDeque::with_capacity(Self::TAPS + 1),
+            steps: Self::DEFAULT_STEPS,
+            tracks: Self::DEFAULT_TRACKS,
+            edits,
+            engine: Arc::new(Mutex::new(Engine {
+                edits: consumer,
+                pulse: None,
+                tracks: (0..Self::DEFAULT_TRACKS)
+                    .map(|_| Track::new(Self::DEFAULT_STEPS))
+                    .collect(),
+            })),
+        }
+    }
+
+    // set the number of tracks, up to one per MIDI channel. extra tracks are
+    // removed with their step data. ignored while running.
+    pub fn with_tracks(&mut self, tracks: NonZeroU8) -> &mut Self {
+        if self.clock.borrow().is_running() {
+            return self;
+        }
+        self.tracks = usize::from(tracks.get()).min(Self::MAX_TRACKS);
+        if let Ok(mut engine) = self.engine.lock() {
+            engine.resize(self.tracks, self.steps);
+        }
         self
     }
 
+    // set the pattern length in steps, up to 128. steps past the new length
+    // are removed with their data. ignored while running, since the clock
+    // thread keeps the length it started with.
+    pub fn with_steps(&mut self, steps: NonZeroU8) -> &mut Self {
+        if self.clock.borrow().is_running() {
+            return self;
+        }
+        self.steps = usize::from(steps.get()).min(Self::MAX_STEPS);
+        if let Ok(mut engine) = self.engine.lock() {
+            engine.resize(self.tracks, self.steps);
+        }
+        self
+    }
+
+    pub fn with_tempo(&mut self, bpm: Tempo) -> &mut Self {
+        self.set_clock_period(bpm.into());
+        self
+    }
+
+    // change tempo while running. takes effect at the next clock tick.
+    pub fn set_tempo(&mut self, bpm: Tempo) {
+        self.set_clock_period(bpm.into());
+    }
+
+    // set tempo from the average interval between recent taps. returns the
+    // new tempo, or None for the first tap or a tempo out of bounds.
+    pub fn tap(&mut self) -> Option<Tempo> {
+        self.tap_at(Instant::now())
+    }
+
+    fn tap_at(&mut self, now: Instant) -> Option<Tempo> {
+        if let Some(last) = self.taps.back() {
+            if now.saturating_duration_since(*last) > Self::TAP_TIMEOUT {
+                self.taps.clear();
+            }
+        }
+        if self.taps.len() > Self::TAPS {
+            self.taps.pop_front();
+        }
+        self.taps.push_back(now);
+
+        let first = self.taps.front()?;
+        let intervals = self.taps.len() as u32 - 1;
+        if intervals == 0 {
+            return None;
+        }
+        let interval = now.saturating_duration_since(*first) / intervals;
+        let bpm = Tempo::try_from(60.0 / interval.as_secs_f64()).ok()?;
+        self.set_tempo(bpm);
+        Some(bpm)
+    }
+
+    // gradually change tempo over a number of steps. the clock period is
+    // recomputed on every tick along the curve. a ramp too long to count in
+    // ticks is cut to the longest one that can be counted.
+    pub fn ramp_tempo(&mut self, bpm: Tempo, steps: usize, curve: Curve) {
+        let period = self.period_for(bpm.into());
+        let ticks = steps.saturating_mul(self.get_pulses_per_step());
+        self.clock.get_mut().ramp_period(period, ticks, curve);
+    }
+
+    // set the clock resolution in pulses (ticks) per quarter note, keeping the
+    // current tempo. rounded down to a whole number of pulses per step.
+    pub fn with_ppqn(&mut self, ppqn: NonZeroU16) -> &mut Self {
+        let bpm = self.get_tempo();
+        let steps_per_beat = Self::STEPS_PER_BEAT as u16;
+        self.ppqn = (ppqn.get() / steps_per_beat).max(1) * steps_per_beat;
+        self.set_clock_period(bpm);
+        self
+    }
+
+    // beats per min to pulses per min to period in seconds
+    fn period_for(&self, bpm: f64) -> Duration {
+        Duration::from_secs_f64(60.0 / bpm / self.ppqn as f64)
+    }
+
+    fn set_clock_period(&mut self, bpm: f64) {
+        let period = self.period_for(bpm);
+        self.clock.get_mut().set_period(period);
+    }
+
+    // emit TimingClock events at 24 PPQN, Start/Stop/Continue on start() and
+    // pause(), and SongPosition when the position changes. this needs a
+    // resolution of at least 24 PPQN to keep the clock rate correct.
+    pub fn with_midi_clock(&mut self, enabled: bool) -> &mut Self {
+        self.midi_clock = enabled;
+        self
+    }
+
+    // delay every second step of each pair for all tracks. the delay is
+    // rounded to whole clock ticks, so use a higher PPQN for finer swing.
+    pub fn with_swing(&mut self, swing: Swing) -> &mut Self {
+        self.set_swing(swing);
+        self
+    }
+
+    // change swing for all tracks while running
+    pub fn set_swing(&mut self, swing: Swing) {
+        self.swing.store(swing.into(), SeqCst);
+    }
+
+    pub fn get_swing(&self) -> Swing {
+        Swing::try_from(self.swing.load(SeqCst)).unwrap_or_default()
+    }
+
+    // override swing for one track. None follows the sequencer swing again.
+    pub fn set_track_swing(&mut self, track: u4, swing: Option<Swing>) {
+        self.edit(Edit::TrackSwing(track, swing));
+    }
+
+    // loop one track over its first steps, so tracks of different lengths
+    // play against each other. limited to the pattern length. None plays the
+    // whole pattern again.
+    pub fn set_track_length(&mut self, track: u4, length: Option<NonZeroU8>) {
+        self.edit(Edit::TrackLength(track, length));
+    }
+
+    // play one track faster or slower than the others. a track step lasts
+    // the pulses of a sequencer step times divider over multiplier.
+    pub fn set_track_rate(&mut self, track: u4, rate: Rate) {
+        self.edit(Edit::TrackRate(track, rate));
+    }
+
+    // change the order one track plays its steps in
+    pub fn set_track_direction(&mut self, track: u4, direction: Direction) {
+        self.edit(Edit::TrackDirection(track, direction));
+    }
+
+    // clock tick of a track step counted from the first step, including swing
+    // on the second step of a pair. rounded to the nearest tick.
+    fn step_tick(step: usize, swing: Swing, pulses: usize, rate: Rate) -> usize {
+        let (multiplier, divider) = (rate.multiplier() as usize, rate.divider() as usize);
+        // in hundredths of a tick, times the multiplier
+        let mut tick = 100 * step * pulses * divider;
+        if step % 2 == 1 {
+            tick = tick + 2 * pulses * divider * u8::from(swing) as usize - 100 * pulses * divider;
+        }
+        (tick + 50 * multiplier) / (100 * multiplier)
+    }
+
+    // clock ticks to move a note from the grid, rounded to the nearest tick
+    fn nudge_ticks(offset: Nudge, pulses: usize, rate: Rate) -> isize {
+        let ticks = pulses as f64 * rate.divider() as f64 / rate.multiplier() as f64;
+        (i8::from(offset) as f64 * ticks / 100.0).round() as isize
+    }
+
+    // the events are copied into a new Vec for each call. use on_step_slice()
+    // to keep the clock thread free of allocations.
     pub fn on_step<F>(&mut self, callback: F) -> &mut Self
     where
         F: Fn(usize, Vec<Event>) + Send + Sync + 'static,
+    {
+        self.on_step_slice(move |step, events| callback(step, events.to_vec()))
+    }
+
+    // like on_step(), but borrows the events from a buffer reused on every tick
+    pub fn on_step_slice<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(usize, &[Event]) + Send + Sync + 'static,
     {
         self.callback = Arc::new(callback);
         self
     }
 
-    pub fn build(&self) -> Self {
-        // Clock is wrapped in an Rc so we can can use Copy to create a new and
-        // final Sequencer. This means we have to perform an extra step to
-        // request a mutable reference whenever we want to modify a Clock
-        // property. However, since the &self Clock reference is dropped after
-        // this method, we're guaranteed to only have once Clock reference, so
-        // all get_mut() calls will succeed.
-        let mut sequencer = Self::default();
-        sequencer.callback = self.callback.clone();
-        sequencer.clock = self.clock.clone();
-        sequencer
+    pub fn build(&self) -> Self
+    where
+        C: Clone,
+    {
+        // Clock is wrapped in an Rc so we can can use Copy to create a new and
+        // final Sequencer. This means we have to perform an extra step to
+        // request a mutable reference whenever we want to modify a Clock
+        // property. However, since the &self Clock reference is dropped after
+        // this method, we're guaranteed to only have once Clock reference, so
+        // all get_mut() calls will succeed.
+        let mut sequencer = Self {
+            callback: self.callback.clone(),
+            midi_clock: self.midi_clock,
+            ppqn: self.ppqn,
+            swing: Arc::new(AtomicU8::new(self.swing.load(SeqCst))),
+            ..Self::with_clock(self.clock.borrow().clone())
+        };
+        let tracks = u8::try_from(self.tracks).unwrap();
+        let steps = u8::try_from(self.steps).unwrap();
+        sequencer
+            .with_tracks(NonZeroU8::new(tracks).unwrap())
+            .with_steps(NonZeroU8::new(steps).unwrap());
+        sequencer
+    }
+
+    pub fn start(&mut self) {
+        if self.clock.borrow().is_running() {
+            return; // already running
+        }
+
+        // variables to move into closure
+        let callback = self.callback.clone();
+        let engine = self.engine.clone();
+        let dropped = self.dropped.clone();
+        let pulses = self.get_pulses_per_step();
+        let length = self.steps;
+        let ppqn = self.ppqn as usize;
+        let skipped = self.skipped;
+        let swing = self.swing.clone();
+        let midi_clock = self.midi_clock;
+        let first_tick = AtomicBool::new(true);
+        // only locked by the clock thread, one tick at a time
+        let buffer = Mutex::new(Vec::with_capacity(Self::EVENTS));
+
+        // transport messages to send with the first tick
+        let transport = if !midi_clock {
+            Vec::new()
+        } else if self.clock.borrow().get_ticks() == 0 {
+            vec![Event::Start]
+        } else {
+            vec![self.song_position(), Event::Continue]
+        };
+
+        self.clock.get_mut().on_tick(move |tick| {
+            // tick should always be > 0, but check anyways
+            let pulse = tick.saturating_sub(1) + skipped;
+            let step = (pulse / pulses) % length;
+            let mut events = buffer.lock().unwrap();
+            events.clear();
+
+            if first_tick.swap(false, SeqCst) {
+                events.extend_from_slice(&transport);
+            }
+            // spread 24 timing clocks per quarter note over the clock ticks
+            if midi_clock && pulse * Self::MIDI_CLOCK_PPQN % ppqn < Self::MIDI_CLOCK_PPQN {
+                events.push(Event::TimingClock);
+            }
+
+            // We need mutable access in order to apply edits and update each
+            // Track's Voice params. Other threads queue edits instead of
+            // locking, so the lock is free unless the edit queue overflowed.
+            // Never wait for it. If it is held, report the tick as dropped.
+            let swing = Swing::try_from(swing.load(SeqCst)).unwrap_or_default();
+            if let Ok(mut engine) = engine.try_lock() {
+                engine.apply_edits(&mut events);
+                // note offs are due at a tick, so move them if the tick moved back
+                if let Some(last) = engine.pulse.replace(pulse) {
+                    if pulse <= last {
+                        engine.rewind(last, pulse);
+                    }
+                }
+                for (i, track) in engine.tracks.iter_mut().enumerate() {
+                    // channel is same as track number
+                    let channel = u4::try_from(i as u8).unwrap();
+                    let swing = track.swing.unwrap_or(swing);
+                    // each track loops over its own length from the same tick,
+                    // never past the steps it has
+                    let length = track.length.map_or(length, |l| l.min(length));
+                    // swing and microtiming move events up to a step away
+                    // from the grid, so look at the previous and next step
+                    // too. faster tracks can have several steps in a tick.
+                    let rate = track.rate;
+                    let (multiplier, divider) =
+                        (rate.multiplier() as usize, rate.divider() as usize);
+                    let first = pulse * multiplier / (pulses * divider);
+                    let last = ((pulse + 1) * multiplier - 1) / (pulses * divider);
+                    let nearby = first.saturating_sub(1)..=(last + 1);
+                    // first do controller_changes, since this will affect
+                    // the sound of the Voice for upcoming notes
+                    for n in nearby.clone() {
+                        if Self::step_tick(n, swing, pulses, rate) != pulse {
+                            continue;
+                        }
+                        let position = track.position(i, n, length);
+                        for param in &track.steps[position].params {
+                            track.voice.set_param(param);
+                            events.push(Event::ControllerChange {
+                                channel,
+                                controller: param.controller.number(),
+                                value: param.value,
+                            });
+                        }
+                    }
+                    // next do note_offs that are due
+                    for (pitch, due) in track.note_offs.iter_mut().enumerate() {
+                        if matches!(due, Some(due) if *due <= pulse) {
+                            *due = None;
+                            let pitch = u7::try_from(pitch).unwrap();
+                            events.push(Event::NoteOff { channel, pitch });
+                        }
+                    }
+                    // finally do note_ons and queue up note_offs for later
+                    for n in nearby.clone() {
+                        let tick = Self::step_tick(n, swing, pulses, rate);
+                        let position = track.position(i, n, length);
+                        for note in &track.steps[position].note_ons {
+                            // early notes of the first step play right away
+                            let nudge = Self::nudge_ticks(note.offset, pulses, rate);
+                            if (tick as isize + nudge).max(0) as usize != pulse {
+                                continue;
+                            }
+                            // one note per pitch, so end a note that is
+                            // still sounding before playing it again
+                            let sounding = &mut track.note_offs[usize::from(note.pitch)];
+                            if sounding.take().is_some() {
+                                events.push(Event::NoteOff {
+                                    channel,
+                                    pitch: note.pitch,
+                                });
+                            }
+                            events.push(Event::NoteOn {
+                                channel,
+                                pitch: note.pitch,
+                                velocity: note.velocity,
+                            });
+                            // a duration of zero gets an immediate note_off.
+                            // no note lasts longer than the track loop.
+                            let duration = usize::from(note.duration).min(length);
+                            if duration == 0 {
+                                events.push(Event::NoteOff {
+                                    channel,
+                                    pitch: note.pitch,
+                                });
+                            } else {
+                                // at least a tick, even on fast tracks
+                                let ticks =
+                                    (duration * pulses * divider + multiplier / 2) / multiplier;
+                                *sounding = Some(pulse + ticks.max(1));
+                            }
+                        }
+                    }
+                }
+            } else {
+                dropped.fetch_add(1, SeqCst);
+                events.push(Event::Dropped);
+            }
+
+            // always report a new step, but only report other ticks with events
+            if pulse.is_multiple_of(pulses) || !events.is_empty() {
+                callback(step, &events);
+            }
+        });
+        self.clock.get_mut().start();
+    }
+
+    pub fn pause(&mut self) {
+        if !self.is_running() {
+            return; // already paused
+        }
+        self.clock.get_mut().stop();
+        // the rest of the step is thrown away, so the song position sent on
+        // the next start() is where playing picks up again. an external
+        // clock picks up wherever its master is instead.
+        if !self.clock.borrow().is_external() {
+            let pulses = self.get_pulses_per_step();
+            let ticks = self.clock.borrow().get_ticks() + self.skipped;
+            self.skipped += (pulses - ticks % pulses) % pulses;
+        }
+        // notes would be left sounding until after the next start()
+        let mut events = Vec::new();
+        if let Ok(mut engine) = self.engine.lock() {
+            engine.note_offs(&mut events);
+        }
+        if self.midi_clock {
+            events.push(Event::Stop);
+        }
+        if !events.is_empty() {
+            (self.callback)(usize::from(self.get_steps().0), &events);
+        }
+    }
+
+    // silence every track channel with All Sound Off and All Notes Off, and
+    // forget the notes waiting to end. with note_offs, also send a note off
+    // for every pitch, for devices that ignore channel mode messages. while
+    // running, these are sent by the clock thread on the next tick, so they
+    // never cut off a note that tick starts.
+    pub fn panic(&mut self, note_offs: bool) {
+        self.edit(Edit::Panic(note_offs));
+    }
+
+    // rewind to the first step. does nothing while running.
+    pub fn reset(&mut self) {
+        if self.is_running() {
+            return;
+        }
+        self.clock.get_mut().reset();
+        self.skipped = 0;
+        if self.midi_clock {
+            (self.callback)(0, &[self.song_position()]);
+        }
+    }
+
+    // position of the next step in MIDI beats, which are the same as steps
+    fn song_position(&self) -> Event {
+        let position = self.get_steps().1 % (u14::MAX as usize + 1);
+        Event::SongPosition {
+            position: u14::try_from(position).unwrap(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.clock.borrow().is_running()
+    }
+
+    // returns current step number within the pattern and total number of steps
+    pub fn get_steps(&self) -> (u7, usize) {
+        let ticks = self.clock.borrow().get_ticks() + self.skipped;
+        if ticks == 0 {
+            return (u7::ZERO, 0);
+        }
+        let steps = (ticks - 1) / self.get_pulses_per_step() + 1;
+        (u7::try_from((steps - 1) % self.steps).unwrap(), steps)
+    }
+
+    // returns the pattern length in steps
+    pub fn get_length(&self) -> usize {
+        self.steps
+    }
+
+    // returns tempo in beats per minute derived from the clock period
+    pub fn get_tempo(&self) -> f64 {
+        60.0 / self.clock.borrow().get_period().as_secs_f64() / self.ppqn as f64
+    }
+
+    // returns the number of ticks whose step data could not be read in time
+    pub fn get_dropped(&self) -> usize {
+        self.dropped.load(SeqCst)
+    }
+
+    // returns the number of tracks
+    pub fn get_tracks(&self) -> usize {
+        self.tracks
+    }
+
+    // returns timing measurements of the clock
+    pub fn get_stats(&self) -> Stats {
+        self.clock.borrow().stats()
+    }
+
+    // returns clock ticks per quarter note
+    pub fn get_ppqn(&self) -> u16 {
+        self.ppqn
+    }
+
+    // returns clock ticks per step
+    pub fn get_pulses_per_step(&self) -> usize {
+        (self.ppqn / Self::STEPS_PER_BEAT as u16) as usize
+    }
+
+    // queue a change to step data. the clock thread applies it at the next
+    // tick, without waiting on this thread. while the clock is stopped, or if
+    // the queue is full because the clock is not ticking, it is applied here.
+    fn edit(&mut self, mut edit: Edit) {
+        let mut events = Vec::new();
+        while let Err(full) = self.edits.push(edit) {
+            edit = full;
+            if let Ok(mut engine) = self.engine.lock() {
+                engine.apply_edits(&mut events);
+            }
+        }
+        if !self.is_running() {
+            if let Ok(mut engine) = self.engine.lock() {
+                engine.apply_edits(&mut events);
+            }
+        }
+        // events from edits applied here are sent from this thread instead
+        if !events.is_empty() {
+            (self.callback)(usize::from(self.get_steps().0), &events);
+        }
+    }
+
+    // add note to step for track. overwrites an existing note with the same pitch.
+    pub fn add_note(&mut self, track: u4, step: u7, note: Note) {
+        self.edit(Edit::AddNote(track, step, note));
+    }
+
+    // sets the timing offset of a note for step in track by matching pitch.
+    // does nothing if it does not exist.
+    pub fn nudge_note(&mut self, track: u4, step: u7, note: Note) {
+        self.edit(Edit::NudgeNote(track, step, note));
+    }
+
+    // removes a note for step in track by matching pitch. does nothing if not does not exist.
+    pub fn delete_note(&mut self, track: u4, step: u7, note: Note) {
+        self.edit(Edit::DeleteNote(track, step, note));
+    }
+
+    // adds a parameter change to step for track. overwrites an existing parameter with same controller.
+    pub fn set_param(&mut self, track: u4, step: u7, param: Param) {
+        self.edit(Edit::SetParam(track, step, param));
+    }
+
+    // removes a parameter change for step in track by matching controller type.
+    pub fn clear_param(&mut self, track: u4, step: u7, param: Param) {
+        self.edit(Edit::ClearParam(track, step, param));
+    }
+}
+
+impl<C: Clock + Default> Default for Sequencer<C> {
+    fn default() -> Self {
+        Self::with_clock(C::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::clock::MidiClock;
+    use crate::types::Length;
+    use std::io::{pipe, Write};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        mpsc::channel,
+        Arc,
+    };
+
+    #[test]
+    fn set_tempo() {
+        let tempo = Tempo::try_from(60.0).unwrap(); // bpm
+        let sequencer = Sequencer::with_clock(SystemClock::new())
+            .with_tempo(tempo)
+            .build();
+        let period = sequencer.clock.borrow().get_period();
+        assert_eq!(period, Duration::from_secs_f64(1.0 / 24.0));
+    }
+
+    #[test]
+    fn live_tempo() {
+        let clock = ManualClock::new();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_tempo(Tempo::try_from(120.0).unwrap())
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        sequencer.start();
+        clock.advance(pulses);
+        sequencer.set_tempo(Tempo::try_from(87.5).unwrap());
+        assert!(sequencer.is_running());
+        assert!((sequencer.get_tempo() - 87.5).abs() < 1e-3);
+        assert_eq!(
+            clock.get_period(),
+            Duration::from_secs_f64(60.0 / 87.5 / 24.0)
+        );
+        clock.advance(pulses);
+        assert_eq!(sequencer.get_steps().1, 2); // stepping continues uninterrupted
+    }
+
+    #[test]
+    fn tap_tempo() {
+        let clock = ManualClock::new();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_tempo(Tempo::try_from(60.0).unwrap())
+            .build();
+        let beat = Duration::from_millis(500); // 120 bpm
+        let mut now = Instant::now();
+        assert_eq!(sequencer.tap_at(now), None);
+        for _ in 0..<Sequencer>::TAPS {
+            now += beat;
+            assert_eq!(sequencer.tap_at(now), Tempo::try_from(120.0).ok());
+        }
+        assert_eq!(clock.get_period(), Duration::from_secs_f64(0.5 / 24.0));
+
+        // only the last taps are averaged
+        for _ in 0..<Sequencer>::TAPS {
+            now += beat / 2;
+            sequencer.tap_at(now);
+        }
+        assert!((sequencer.get_tempo() - 240.0).abs() < 1e-3);
+
+        // a long gap starts over
+        now += <Sequencer>::TAP_TIMEOUT * 2;
+        assert_eq!(sequencer.tap_at(now), None);
+        now += beat * 2;
+        assert_eq!(sequencer.tap_at(now), Tempo::try_from(60.0).ok());
+    }
+
+    #[test]
+    fn ramp_tempo() {
+        let clock = ManualClock::new();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_tempo(Tempo::try_from(120.0).unwrap())
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        sequencer.start();
+        sequencer.ramp_tempo(Tempo::try_from(140.0).unwrap(), 8, Curve::Linear);
+        clock.advance(4 * pulses);
+        assert!((sequencer.get_tempo() - 130.0).abs() < 1e-3);
+        clock.advance(4 * pulses);
+        assert!((sequencer.get_tempo() - 140.0).abs() < 1e-3);
+        clock.advance(4 * pulses);
+        assert!((sequencer.get_tempo() - 140.0).abs() < 1e-3);
+        // too many ticks to count, so the tempo barely moves
+        sequencer.ramp_tempo(Tempo::try_from(60.0).unwrap(), usize::MAX, Curve::Linear);
+        clock.advance(pulses);
+        assert!((sequencer.get_tempo() - 140.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn midi_clock() {
+        let clock = ManualClock::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_ppqn(NonZeroU16::new(96).unwrap())
+            .with_midi_clock(true)
+            .on_step(move |_, events| log.lock().unwrap().extend(events))
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        let timing_clocks = |events: &[Event]| {
+            events
+                .iter()
+                .filter(|event| **event == Event::TimingClock)
+                .count()
+        };
+
+        sequencer.start();
+        clock.advance(4 * pulses); // one beat
+        sequencer.pause();
+        {
+            let events = events.lock().unwrap();
+            assert_eq!(events[0], Event::Start);
+            assert_eq!(timing_clocks(&events), 24);
+            assert_eq!(events[events.len() - 1], Event::Stop);
+        }
+
+        events.lock().unwrap().clear();
+        sequencer.start();
+        assert!(events.lock().unwrap().is_empty()); // sent with first tick
+        clock.advance(pulses);
+        sequencer.pause();
+        {
+            let events = events.lock().unwrap();
+            let position = u14::try_from(4_u16).unwrap();
+            assert_eq!(events[0], Event::SongPosition { position });
+            assert_eq!(events[1], Event::Continue);
+            assert_eq!(timing_clocks(&events), 6);
+        }
+
+        // paused part way into a step, playing picks up at the next one
+        sequencer.start();
+        clock.advance(pulses / 2);
+        sequencer.pause();
+        let step = u7::try_from(6_u8).unwrap();
+        sequencer.add_note(u4::ZERO, step, Note::from_pitch(u7::ZERO));
+        events.lock().unwrap().clear();
+        sequencer.start();
+        clock.advance(1);
+        sequencer.pause();
+        {
+            let events = events.lock().unwrap();
+            let position = u14::try_from(6_u16).unwrap();
+            assert_eq!(events[0], Event::SongPosition { position });
+            assert_eq!(events[1], Event::Continue);
+            assert_eq!(events[2], Event::TimingClock);
+            assert!(matches!(events[3], Event::NoteOn { .. }));
+        }
+
+        events.lock().unwrap().clear();
+        sequencer.reset();
+        sequencer.start();
+        clock.advance(1);
+        let events = events.lock().unwrap();
+        let position = u14::ZERO;
+        assert_eq!(events[0], Event::SongPosition { position });
+        assert_eq!(events[1], Event::Start);
+    }
+
+    #[test]
+    fn set_ppqn() {
+        let tempo = Tempo::try_from(120.0).unwrap(); // bpm
+        let sequencer = Sequencer::with_clock(ManualClock::new())
+            .with_tempo(tempo)
+            .with_ppqn(NonZeroU16::new(96).unwrap())
+            .build();
+        assert_eq!(sequencer.get_ppqn(), 96);
+        assert_eq!(sequencer.get_pulses_per_step(), 24);
+        assert!((sequencer.get_tempo() - 120.0).abs() < 1e-3);
+        let period = sequencer.clock.borrow().get_period();
+        assert_eq!(period, Duration::from_secs_f64(0.5 / 96.0));
+
+        // rounded down to a whole number of pulses per step
+        let sequencer = Sequencer::with_clock(ManualClock::new())
+            .with_ppqn(NonZeroU16::new(2).unwrap())
+            .build();
+        assert_eq!(sequencer.get_ppqn(), 4);
+    }
+
+    #[test]
+    fn swing() {
+        let clock = ManualClock::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_swing(Swing::try_from(75).unwrap())
+            .on_step(move |step, events| {
+                for event in events {
+                    if let Event::NoteOn { channel, .. } = event {
+                        log.lock().unwrap().push((usize::from(channel), step));
+                    }
+                }
+            })
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        for track in 0..2_u8 {
+            for step in 0..2_u8 {
+                let track = u4::try_from(track).unwrap();
+                let step = u7::try_from(step).unwrap();
+                sequencer.add_note(track, step, Note::from_pitch(u7::ZERO));
+            }
+        }
+        sequencer.set_track_swing(u4::try_from(1_u8).unwrap(), Some(Swing::STRAIGHT));
+        sequencer.start();
+
+        // first step of the pair stays on the grid
+        clock.advance(1);
+        assert_eq!(*events.lock().unwrap(), vec![(0, 0), (1, 0)]);
+
+        // second step is straight for track 1 and three quarters through the pair for track 0
+        clock.advance(pulses);
+        assert_eq!(events.lock().unwrap()[2..], [(1, 1)]);
+        clock.advance(pulses / 2 - 1);
+        assert_eq!(events.lock().unwrap().len(), 3);
+        clock.advance(1);
+        assert_eq!(events.lock().unwrap()[3..], [(0, 1)]);
+
+        // follows the sequencer swing again
+        sequencer.set_track_swing(u4::try_from(1_u8).unwrap(), None);
+        sequencer.set_swing(Swing::STRAIGHT);
+        assert_eq!(sequencer.get_swing(), Swing::STRAIGHT);
+        clock.advance(<Sequencer>::DEFAULT_STEPS * pulses - pulses - pulses / 2);
+        assert_eq!(events.lock().unwrap()[4..], [(0, 0), (1, 0)]);
+        clock.advance(pulses);
+        assert_eq!(events.lock().unwrap()[6..], [(0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn nudge_notes() {
+        let clock = ManualClock::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .on_step(move |_, events| log.lock().unwrap().extend(events))
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        // pitch is same as track number
+        for (track, step, offset) in &[(0_u8, 1_u8, 50), (1, 1, -50), (2, 0, -50)] {
+            let pitch = u7::try_from(*track).unwrap();
+            let track = u4::try_from(*track).unwrap();
+            let step = u7::try_from(*step).unwrap();
+            let note = Note {
+                duration: Length::try_from(1_u8).unwrap(),
+                ..Note::from_pitch(pitch)
+            };
+            sequencer.add_note(track, step, note);
+            let offset = Nudge::try_from(*offset).unwrap();
+            sequencer.nudge_note(
+                track,
+                step,
+                Note {
+                    offset,
+                    ..Note::from_pitch(pitch)
+                },
+            );
+        }
+
+        // record the clock tick of each note event
+        let mut timeline = Vec::new();
+        sequencer.start();
+        for pulse in 0..<Sequencer>::DEFAULT_STEPS * pulses {
+            clock.advance(1);
+            for event in events.lock().unwrap().drain(..) {
+                match event {
+                    Event::NoteOn { pitch, .. } => timeline.push((pulse, u8::from(pitch), true)),
+                    Event::NoteOff { pitch, .. } => timeline.push((pulse, u8::from(pitch), false)),
+                    _ => {}
+                }
+            }
+        }
+
+        let late = pulses + pulses / 2;
+        let early = pulses / 2;
+        let wrapped = <Sequencer>::DEFAULT_STEPS * pulses - pulses / 2;
+        assert_eq!(
+            timeline,
+            vec![
+                (0, 2, true), // early notes on the first step play right away
+                (early, 1, true),
+                (pulses, 2, false),
+                (late, 0, true),
+                (early + pulses, 1, false),
+                (late + pulses, 0, false),
+                (wrapped, 2, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn dropped_steps() {
+        let clock = ManualClock::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .on_step(move |_, events| log.lock().unwrap().push(events))
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        sequencer.add_note(u4::ZERO, u7::ZERO, Note::from_pitch(u7::ZERO));
+        sequencer.start();
+        {
+            let _engine = sequencer.engine.lock().unwrap(); // hold the lock
+            clock.advance(1);
+        }
+        assert_eq!(sequencer.get_dropped(), 1);
+        assert_eq!(events.lock().unwrap().pop(), Some(vec![Event::Dropped]));
+
+        // an empty step is not a dropped step
+        clock.advance(pulses);
+        assert_eq!(sequencer.get_dropped(), 1);
+        assert_eq!(events.lock().unwrap().pop(), Some(vec![]));
     }
 
-    pub fn start(&mut self) {
-        if self.clock.borrow().is_running() {
-            return; // already running
+    #[test]
+    fn queued_edits() {
+        let clock = ManualClock::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .on_step(move |_, events| log.lock().unwrap().extend(events))
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        let pitch = u7::try_from(u7::MAX).unwrap();
+        sequencer.start();
+        {
+            // edits while running do not wait for the clock thread
+            let engine = sequencer.engine.clone();
+            let _engine = engine.lock().unwrap();
+            for step in 0..<Sequencer>::DEFAULT_STEPS {
+                let step = u7::try_from(step).unwrap();
+                sequencer.add_note(u4::ZERO, step, Note::from_pitch(u7::ZERO));
+                sequencer.delete_note(u4::ZERO, step, Note::from_pitch(u7::ZERO));
+            }
+            sequencer.add_note(u4::ZERO, u7::ZERO, Note::from_pitch(pitch));
         }
+        clock.advance(<Sequencer>::DEFAULT_STEPS * pulses);
+        let note_ons: Vec<Event> = events
+            .lock()
+            .unwrap()
+            .drain(..)
+            .filter(|event| matches!(event, Event::NoteOn { .. }))
+            .collect();
+        let velocity = u7::ZERO;
+        let channel = u4::ZERO;
+        assert_eq!(
+            note_ons,
+            vec![Event::NoteOn {
+                channel,
+                pitch,
+                velocity
+            }]
+        );
 
-        // variables to move into closure
-        let callback = self.callback.clone();
-        let tracks = self.tracks.clone();
-        let period = self.clock.borrow().get_period();
+        // overflowing the queue applies edits right away instead of losing them
+        for _ in 0..<Sequencer>::EDITS + 1 {
+            sequencer.delete_note(u4::ZERO, u7::ZERO, Note::from_pitch(pitch));
+        }
+        clock.advance(<Sequencer>::DEFAULT_STEPS * pulses);
+        assert!(events.lock().unwrap().is_empty());
+        assert_eq!(sequencer.get_dropped(), 0);
+    }
 
-        self.clock.get_mut().on_tick(move |tick| {
-            let step = if tick == 0 {
-                0 // tick should always be > 0, but check anyways
-            } else {
-                (tick - 1) % Sequencer::STEPS
-            };
-            let mut events: Vec<Event> = Vec::new();
-
-            // We need mutable access in order to update each Track's Voice
-            // params. We could block on the mutex and risk missing Clock ticks
-            // if another thread is holding the lock or we could bail and not
-            // report events for this step. Instead we will poll the mutex lock
-            // for half of a clock period before giving up.
-            let now = Instant::now();
-            while now.elapsed() < (period / 2) {
-                if let Ok(mut tracks) = tracks.try_lock() {
-                    for (i, track) in tracks.iter_mut().enumerate() {
-                        // channel is same as track number
-                        let channel = u4::try_from(i as u8).unwrap();
-                        // first do controller_changes, since this will affect
-                        // the sound of the Voice for upcoming notes
-                        for param in &track.steps[step].params {
-                            track.voice.set_param(param);
-                            events.push(Event::ControllerChange {
-                                channel,
-                                controller: param.controller.number(),
-                                value: param.value,
-                            });
-                        }
-                        // next do note_offs to clear the vector for this step
-                        for pitch in track.steps[step].note_offs.drain(..) {
-                            events.push(Event::NoteOff { channel, pitch });
-                        }
-                        // finally do note_ons and queue up note_offs for later
-                        for note in &track.steps[step].note_ons {
-                            events.push(Event::NoteOn {
-                                channel,
-                                pitch: note.pitch,
-                                velocity: note.velocity,
-                            });
-                            // a duration of zero gets an immediate note_off
-                            if u8::from(note.duration) == 0 {
-                                events.push(Event::NoteOff {
-                                    channel,
-                                    pitch: note.pitch,
-                                });
-                            } else {
-                                track.steps[(step + usize::from(note.duration)) % Sequencer::STEPS]
-                                    .note_offs
-                                    .push(note.pitch);
-                            }
-                        }
+    #[test]
+    fn set_tracks() {
+        let clock = ManualClock::new();
+        let channels = Arc::new(Mutex::new(Vec::new()));
+        let log = channels.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_tracks(NonZeroU8::new(u8::MAX).unwrap())
+            .on_step(move |_, events| {
+                for event in events {
+                    if let Event::NoteOn { channel, .. } = event {
+                        log.lock().unwrap().push(u8::from(channel));
                     }
-                    break;
                 }
-            }
+            })
+            .build();
+        assert_eq!(sequencer.get_tracks(), <Sequencer>::MAX_TRACKS); // one per channel
+        for track in 0..<Sequencer>::MAX_TRACKS {
+            let track = u4::try_from(track).unwrap();
+            sequencer.add_note(track, u7::ZERO, Note::from_pitch(u7::ZERO));
+        }
+        sequencer.start();
+        clock.advance(1);
+        assert_eq!(*channels.lock().unwrap(), (0..16).collect::<Vec<u8>>());
 
-            callback(step, events);
-        });
-        self.clock.get_mut().start();
+        // edits for tracks that do not exist are ignored
+        let mut sequencer = Sequencer::with_clock(ManualClock::new())
+            .with_tracks(NonZeroU8::new(2).unwrap())
+            .build();
+        assert_eq!(sequencer.get_tracks(), 2);
+        let track = u4::try_from(2_u8).unwrap();
+        sequencer.add_note(track, u7::ZERO, Note::from_pitch(u7::ZERO));
+        sequencer.set_track_swing(track, None);
     }
 
-    pub fn pause(&mut self) {
-        self.clock.get_mut().stop();
-    }
+    #[test]
+    fn set_steps() {
+        let clock = ManualClock::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_steps(NonZeroU8::new(7).unwrap())
+            .on_step(move |step, events| {
+                for event in events {
+                    match event {
+                        Event::NoteOn { .. } => log.lock().unwrap().push((step, true)),
+                        Event::NoteOff { .. } => log.lock().unwrap().push((step, false)),
+                        _ => {}
+                    }
+                }
+            })
+            .build();
+        assert_eq!(sequencer.get_length(), 7);
+        let pulses = sequencer.get_pulses_per_step();
+        // a note as long as the pattern ends as it starts again
+        let note = Note {
+            duration: Length::try_from(7_u8).unwrap(),
+            ..Note::from_pitch(u7::ZERO)
+        };
+        sequencer.add_note(u4::ZERO, u7::ZERO, note);
+        sequencer.start();
+        clock.advance(10 * pulses);
+        assert_eq!(sequencer.get_steps(), (u7::try_from(2_u8).unwrap(), 10));
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![(0, true), (0, false), (0, true)]
+        );
 
-    pub fn is_running(&self) -> bool {
-        self.clock.borrow().is_running()
-    }
+        // up to 128 steps, and steps past the pattern are ignored
+        let clock = ManualClock::new();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_steps(NonZeroU8::new(u8::MAX).unwrap())
+            .build();
+        assert_eq!(sequencer.get_length(), <Sequencer>::MAX_STEPS);
+        let last = u7::try_from(u7::MAX).unwrap();
+        sequencer.add_note(u4::ZERO, last, Note::from_pitch(u7::ZERO));
+        sequencer.start();
+        clock.advance(<Sequencer>::MAX_STEPS * sequencer.get_pulses_per_step());
+        assert_eq!(sequencer.get_steps().0, last);
 
-    // returns current step number from 0 to 15 and total number of steps
-    pub fn get_steps(&self) -> (u4, usize) {
-        assert_eq!(usize::from(u4::MAX), Sequencer::STEPS - 1);
-        let ticks = self.clock.borrow().get_ticks();
-        if ticks == 0 {
-            return (u4::ZERO, 0);
-        }
-        (u4::try_from((ticks - 1) % Sequencer::STEPS).unwrap(), ticks)
+        let mut sequencer = Sequencer::with_clock(ManualClock::new())
+            .with_steps(NonZeroU8::new(2).unwrap())
+            .build();
+        sequencer.add_note(u4::ZERO, last, Note::from_pitch(u7::ZERO));
     }
 
-    // add note to step for track. overwrites an existing note with the same pitch.
-    pub fn add_note(&mut self, track: u2, step: u4, note: Note) {
-        if let Ok(mut tracks) = self.tracks.lock() {
-            let notes = &mut tracks[usize::from(track)].steps[usize::from(step)].note_ons;
-            notes.retain(|n| n.pitch != note.pitch);
-            notes.push(note);
-        }
+    #[test]
+    fn shrink_steps() {
+        let clock = ManualClock::new();
+        let mut sequencer = Sequencer::with_clock(clock.clone()).build();
+        let pulses = sequencer.get_pulses_per_step();
+        sequencer.start();
+        sequencer.set_track_length(u4::ZERO, NonZeroU8::new(12));
+        clock.advance(pulses);
+        // the pattern can not change size while running
+        sequencer.with_steps(NonZeroU8::new(4).unwrap());
+        assert_eq!(sequencer.get_length(), <Sequencer>::DEFAULT_STEPS);
+        sequencer.pause();
+
+        // a track longer than the new pattern is cut to fit
+        sequencer.with_steps(NonZeroU8::new(8).unwrap());
+        sequencer.start();
+        clock.advance(16 * pulses);
+        assert_eq!(sequencer.get_length(), 8);
     }
 
-    // removes a note for step in track by matching pitch. does nothing if not does not exist.
-    pub fn delete_note(&mut self, track: u2, step: u4, note: Note) {
-        if let Ok(mut tracks) = self.tracks.lock() {
-            let notes = &mut tracks[usize::from(track)].steps[usize::from(step)].note_ons;
-            notes.retain(|n| n.pitch != note.pitch);
+    #[test]
+    fn polymeter() {
+        let clock = ManualClock::new();
+        let counts = Arc::new(Mutex::new([0; 3]));
+        let log = counts.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_tracks(NonZeroU8::new(3).unwrap())
+            .on_step(move |_, events| {
+                for event in events {
+                    if let Event::NoteOn { channel, .. } = event {
+                        log.lock().unwrap()[usize::from(channel)] += 1;
+                    }
+                }
+            })
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        for (track, length) in &[(1_u8, 12), (2, 5)] {
+            let track = u4::try_from(*track).unwrap();
+            sequencer.set_track_length(track, NonZeroU8::new(*length));
+        }
+        for track in 0..3_u8 {
+            let track = u4::try_from(track).unwrap();
+            sequencer.add_note(track, u7::ZERO, Note::from_pitch(u7::ZERO));
         }
+        sequencer.start();
+
+        // each track loops over its own length from the same tick
+        clock.advance(60 * pulses);
+        assert_eq!(*counts.lock().unwrap(), [4, 5, 12]);
+
+        // lengths are limited to the pattern, and None follows it again
+        let track = u4::try_from(2_u8).unwrap();
+        sequencer.set_track_length(track, NonZeroU8::new(u8::MAX));
+        clock.advance(16 * pulses);
+        assert_eq!(counts.lock().unwrap()[2], 13);
+        sequencer.set_track_length(track, None);
+        clock.advance(16 * pulses);
+        assert_eq!(counts.lock().unwrap()[2], 14);
     }
 
-    // adds a parameter change to step for track. overwrites an existing parameter with same controller.
-    pub fn set_param(&mut self, track: u2, step: u4, param: Param) {
-        if let Ok(mut tracks) = self.tracks.lock() {
-            let params = &mut tracks[usize::from(track)].steps[usize::from(step)].params;
-            params.retain(|p| p.controller != param.controller);
-            params.push(param);
+    #[test]
+    fn track_rates() {
+        let clock = ManualClock::new();
+        let counts = Arc::new(Mutex::new([0; 4]));
+        let log = counts.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .on_step(move |_, events| {
+                for event in events {
+                    if let Event::NoteOn { channel, .. } = event {
+                        log.lock().unwrap()[usize::from(channel)] += 1;
+                    }
+                }
+            })
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        let rates = [(1, 1), (1, 3), (3, 2), (8, 1)];
+        for (track, rate) in rates.iter().enumerate() {
+            let track = u4::try_from(track).unwrap();
+            sequencer.set_track_rate(track, Rate::try_from(*rate).unwrap());
+            for step in 0..<Sequencer>::DEFAULT_STEPS {
+                let pitch = u7::try_from(step).unwrap();
+                sequencer.add_note(track, pitch, Note::from_pitch(pitch));
+            }
         }
+        sequencer.start();
+
+        // the fastest track plays two steps in the third tick
+        clock.advance(3);
+        assert_eq!(*counts.lock().unwrap(), [1, 1, 1, 4]);
+        clock.advance(<Sequencer>::DEFAULT_STEPS * pulses - 3);
+        assert_eq!(*counts.lock().unwrap(), [16, 6, 24, 128]);
     }
 
-    // removes a parameter change for step in track by matching controller type.
-    pub fn clear_param(&mut self, track: u2, step: u4, param: Param) {
-        if let Ok(mut tracks) = self.tracks.lock() {
-            let params = &mut tracks[usize::from(track)].steps[usize::from(step)].params;
-            params.retain(|p| p.controller != param.controller);
+    #[test]
+    fn directions() {
+        let clock = ManualClock::new();
+        let played = Arc::new(Mutex::new(vec![Vec::new(); 6]));
+        let log = played.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_tracks(NonZeroU8::new(6).unwrap())
+            .with_steps(NonZeroU8::new(4).unwrap())
+            .on_step(move |_, events| {
+                for event in events {
+                    if let Event::NoteOn { channel, pitch, .. } = event {
+                        log.lock().unwrap()[usize::from(channel)].push(u8::from(pitch));
+                    }
+                }
+            })
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        let directions = [
+            Direction::Forward,
+            Direction::Reverse,
+            Direction::PingPong,
+            Direction::PingPongRepeat,
+            Direction::Random,
+            Direction::Drunk,
+        ];
+        // pitch is same as step number
+        for (track, direction) in directions.iter().enumerate() {
+            let track = u4::try_from(track).unwrap();
+            sequencer.set_track_direction(track, *direction);
+            for step in 0..4_u8 {
+                let step = u7::try_from(step).unwrap();
+                sequencer.add_note(track, step, Note::from_pitch(step));
+            }
         }
-    }
-}
+        sequencer.start();
+        clock.advance(10 * pulses);
 
-impl Default for Sequencer<SystemClock> {
-    fn default() -> Self {
-        Self {
-            clock: RefCell::new(SystemClock::default()),
-            callback: Arc::new(|_, _| {}),
-            tracks: Arc::new(Mutex::new(Default::default())),
+        let played = played.lock().unwrap();
+        assert_eq!(played[0], [0, 1, 2, 3, 0, 1, 2, 3, 0, 1]);
+        assert_eq!(played[1], [3, 2, 1, 0, 3, 2, 1, 0, 3, 2]);
+        assert_eq!(played[2], [0, 1, 2, 3, 2, 1, 0, 1, 2, 3]);
+        assert_eq!(played[3], [0, 1, 2, 3, 3, 2, 1, 0, 0, 1]);
+        assert_eq!(played[4].len(), 10);
+        assert!(played[4].iter().any(|step| *step != played[4][0]));
+        assert_eq!(played[5][0], 0);
+        for pair in played[5].windows(2) {
+            assert!(matches!((pair[1] + 4 - pair[0]) % 4, 1 | 3)); // one step either way
         }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::num::NonZeroU8;
-    use std::sync::{
-        atomic::{AtomicUsize, Ordering::SeqCst},
-        Arc,
-    };
-    use std::thread::sleep;
 
     #[test]
-    fn set_tempo() {
-        let tempo = NonZeroU8::new(60).unwrap(); // bpm
-        let sequencer = Sequencer::new().with_tempo(tempo).build();
-        let period = sequencer.clock.borrow().get_period();
-        assert_eq!(period, Duration::from_millis(250));
+    fn reverse_note_offs() {
+        let clock = ManualClock::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_steps(NonZeroU8::new(4).unwrap())
+            .on_step(move |step, events| {
+                for event in events {
+                    match event {
+                        Event::NoteOn { .. } => log.lock().unwrap().push((step, true)),
+                        Event::NoteOff { .. } => log.lock().unwrap().push((step, false)),
+                        _ => {}
+                    }
+                }
+            })
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        sequencer.set_track_direction(u4::ZERO, Direction::Reverse);
+        let note = Note {
+            duration: Length::try_from(2_u8).unwrap(),
+            ..Note::from_pitch(u7::ZERO)
+        };
+        sequencer.add_note(u4::ZERO, u7::ZERO, note);
+        sequencer.start();
+
+        // the first step plays last and still lasts two steps
+        clock.advance(6 * pulses);
+        assert_eq!(*events.lock().unwrap(), vec![(3, true), (1, false)]);
     }
 
     #[test]
     fn count_steps() {
         let count = 20;
-        let tempo = NonZeroU8::new(150).unwrap(); // bpm
-        let period = Duration::from_millis(100);
+        let clock = ManualClock::new();
         let x = Arc::new(AtomicUsize::new(0));
         let y = x.clone();
-        let mut sequencer = Sequencer::new()
-            .with_tempo(tempo)
+        let mut sequencer = Sequencer::with_clock(clock.clone())
             .on_step(move |_, _| {
                 let _ = y.fetch_add(1, SeqCst);
             })
             .build();
+        let pulses = sequencer.get_pulses_per_step();
         sequencer.start();
-        sleep(count * period - period / 2);
+        clock.advance(count * pulses);
         sequencer.pause();
-        assert_eq!(
-            (u4::try_from(3 as u8).unwrap(), count as usize),
-            sequencer.get_steps()
-        ); // should be on step (count - 1) % 16 after count total step events
-        assert_eq!(count as usize, x.load(SeqCst)); // and count on_step() callbacks
+        assert_eq!((u7::try_from(3_u8).unwrap(), count), sequencer.get_steps()); // should be on step (count - 1) % 16 after count total step events
+        assert_eq!(count, x.load(SeqCst)); // and count on_step() callbacks
     }
 
     #[test]
     fn add_notes() {
-        let tempo = NonZeroU8::new(250).unwrap();
-        let period = Duration::from_millis(60);
+        let clock = ManualClock::new();
         let x = Arc::new(AtomicUsize::new(0));
         let y = x.clone();
-        let mut sequencer = Sequencer::new()
-            .with_tempo(tempo)
+        let mut sequencer = Sequencer::with_clock(clock.clone())
             .on_step(move |step, events| {
                 for event in events {
                     if let Event::NoteOn {
@@ -293,96 +1549,313 @@ mod tests {
                 }
             })
             .build();
-        for track in 0..Sequencer::TRACKS {
-            for step in 0..Sequencer::STEPS {
+        let pulses = sequencer.get_pulses_per_step();
+        for track in 0..<Sequencer>::DEFAULT_TRACKS {
+            for step in 0..<Sequencer>::DEFAULT_STEPS {
                 sequencer.add_note(
-                    u2::try_from(track).unwrap(),
-                    u4::try_from(step).unwrap(),
+                    u4::try_from(track).unwrap(),
+                    u7::try_from(step).unwrap(),
                     Note::from_pitch(u7::try_from(track + step).unwrap()), // pitch value = track + step
                 );
             }
         }
         sequencer.start();
-        sleep(Sequencer::STEPS as u32 * period - period / 2);
+        clock.advance(<Sequencer>::DEFAULT_STEPS * pulses);
         sequencer.pause();
-        assert_eq!(Sequencer::TRACKS * Sequencer::STEPS, x.load(SeqCst));
+        assert_eq!(
+            <Sequencer>::DEFAULT_TRACKS * <Sequencer>::DEFAULT_STEPS,
+            x.load(SeqCst)
+        );
     }
 
     #[test]
     fn remove_notes() {
-        let tempo = NonZeroU8::new(250).unwrap();
-        let period = Duration::from_millis(60);
-        let mut sequencer = Sequencer::new()
-            .with_tempo(tempo)
+        let clock = ManualClock::new();
+        let x = Arc::new(AtomicUsize::new(0));
+        let y = x.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
             .on_step(move |_, events| {
-                assert_eq!(true, events.is_empty());
+                assert!(events.is_empty());
+                let _ = y.fetch_add(1, SeqCst);
             })
             .build();
+        let pulses = sequencer.get_pulses_per_step();
         let mut i: u8 = 0; // add notes
-        for track in 0..Sequencer::TRACKS {
-            for step in 0..Sequencer::STEPS {
+        for track in 0..<Sequencer>::DEFAULT_TRACKS {
+            for step in 0..<Sequencer>::DEFAULT_STEPS {
                 sequencer.add_note(
-                    u2::try_from(track).unwrap(),
-                    u4::try_from(step).unwrap(),
+                    u4::try_from(track).unwrap(),
+                    u7::try_from(step).unwrap(),
                     Note::from_pitch(u7::try_from(i).unwrap()),
                 );
                 i += 1;
             }
         }
         i = 0; // remove notes
-        for track in 0..Sequencer::TRACKS {
-            for step in 0..Sequencer::STEPS {
+        for track in 0..<Sequencer>::DEFAULT_TRACKS {
+            for step in 0..<Sequencer>::DEFAULT_STEPS {
                 sequencer.delete_note(
-                    u2::try_from(track).unwrap(),
-                    u4::try_from(step).unwrap(),
+                    u4::try_from(track).unwrap(),
+                    u7::try_from(step).unwrap(),
                     Note::from_pitch(u7::try_from(i).unwrap()),
                 );
                 i += 1;
             }
         }
         sequencer.start();
-        sleep(Sequencer::STEPS as u32 * period - period / 2);
+        clock.advance(<Sequencer>::DEFAULT_STEPS * pulses);
         sequencer.pause();
+        assert_eq!(<Sequencer>::DEFAULT_STEPS, x.load(SeqCst));
     }
 
     #[test]
     fn note_offs() {
-        let tempo = NonZeroU8::new(250).unwrap();
-        let period = Duration::from_millis(60);
-        let x = Arc::new(AtomicUsize::new(0));
-        let y = x.clone();
-        let mut sequencer = Sequencer::new()
-            .with_tempo(tempo)
+        let clock = ManualClock::new();
+        let note_offs = Arc::new(Mutex::new(Vec::new()));
+        let log = note_offs.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
             .on_step(move |step, events| {
                 for event in events {
-                    if let Event::NoteOff { channel, pitch } = event {
-                        let _ = channel;
-                        let pitch = u8::from(pitch) as i32;
-                        let duration = (step as i32 - pitch).rem_euclid(Sequencer::STEPS as i32);
-                        assert_eq!(duration, pitch);
-                        let _ = y.fetch_add(1, SeqCst);
+                    if let Event::NoteOff { pitch, .. } = event {
+                        log.lock().unwrap().push((step, u8::from(pitch)));
                     }
                 }
             })
             .build();
+        let pulses = sequencer.get_pulses_per_step();
         // add note_ons
-        for track in 0..Sequencer::TRACKS {
-            for step in 0..Sequencer::STEPS {
+        for track in 0..<Sequencer>::DEFAULT_TRACKS {
+            for step in 0..<Sequencer>::DEFAULT_STEPS {
                 sequencer.add_note(
-                    u2::try_from(track).unwrap(),
-                    u4::try_from(step).unwrap(),
+                    u4::try_from(track).unwrap(),
+                    u7::try_from(step).unwrap(),
                     Note {
                         pitch: u7::try_from(step).unwrap(), // pitch value = current step
                         velocity: u7::ZERO,
-                        duration: u4::try_from(step).unwrap(), // duration = current step
+                        duration: Length::try_from(step as u8).unwrap(), // duration = current step
+                        offset: Nudge::ZERO,
                     },
                 );
             }
         }
         sequencer.start();
-        sleep(2 * Sequencer::STEPS as u32 * period - period / 2);
+        clock.advance(2 * <Sequencer>::DEFAULT_STEPS * pulses);
+        let due: Vec<_> = note_offs.lock().unwrap().drain(..).collect();
+        for (step, pitch) in &due {
+            let duration =
+                (*step as i32 - *pitch as i32).rem_euclid(<Sequencer>::DEFAULT_STEPS as i32);
+            assert_eq!(duration, *pitch as i32);
+        }
+        assert_eq!(
+            3 * <Sequencer>::DEFAULT_TRACKS * <Sequencer>::DEFAULT_STEPS / 2,
+            due.len()
+        );
+
+        // notes from the second half of the pattern are still sounding
+        sequencer.pause();
+        let flushed = note_offs.lock().unwrap();
+        assert_eq!(
+            <Sequencer>::DEFAULT_TRACKS * <Sequencer>::DEFAULT_STEPS / 2,
+            flushed.len()
+        );
+        assert!(flushed.iter().all(|(_, pitch)| *pitch >= 8));
+    }
+
+    #[test]
+    fn midi_restart() {
+        let (reader, mut writer) = pipe().unwrap();
+        let (sender, receiver) = channel();
+        let mut sequencer = Sequencer::with_clock(MidiClock::from_reader(reader))
+            .on_step(move |_, events| sender.send(events).unwrap())
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        let note = Note {
+            duration: Length::try_from(16_u8).unwrap(),
+            ..Note::from_pitch(u7::ZERO)
+        };
+        let step = u7::try_from(8_u8).unwrap();
+        sequencer.add_note(u4::ZERO, step, note);
+        sequencer.start();
+        // every event is on a step, so there is one callback per step
+        let mut play = |steps: usize| {
+            let mut stream = vec![0xFA]; // the master starts from the top
+            stream.resize(1 + steps * pulses, 0xF8);
+            writer.write_all(&stream).unwrap();
+            let steps = receiver.iter().take(steps).enumerate();
+            steps
+                .flat_map(|(step, events)| events.into_iter().map(move |event| (step, event)))
+                .collect::<Vec<_>>()
+        };
+        let events = play(9);
+        assert!(matches!(events[..], [(8, Event::NoteOn { .. })]));
+        sequencer.delete_note(u4::ZERO, step, note);
+        // the note still ends 16 steps after it started
+        let events = play(20);
+        assert!(matches!(events[..], [(15, Event::NoteOff { .. })]));
+    }
+
+    #[test]
+    fn midi_pause() {
+        let (reader, mut writer) = pipe().unwrap();
+        let (sender, receiver) = channel();
+        let mut sequencer = Sequencer::with_clock(MidiClock::from_reader(reader))
+            .on_step(move |step, events| sender.send((step, events)).unwrap())
+            .build();
+        let timeout = Duration::from_secs(10);
+        let note = Note {
+            duration: Length::try_from(1_u8).unwrap(),
+            ..Note::from_pitch(u7::ZERO)
+        };
+        sequencer.add_note(u4::ZERO, u7::ZERO, note);
+        sequencer.add_note(u4::ZERO, u7::try_from(2_u8).unwrap(), note);
+        sequencer.start();
+        // the master starts, and the sequencer is paused part way into a step
+        writer.write_all(&[0xFA, 0xF8, 0xF8, 0xF8]).unwrap();
+        let (_, events) = receiver.recv_timeout(timeout).unwrap();
+        assert!(matches!(events[..], [Event::NoteOn { .. }]));
+        sequencer.pause();
+        let (_, events) = receiver.recv_timeout(timeout).unwrap();
+        assert!(matches!(events[..], [Event::NoteOff { .. }]));
+        // the master starts over, so the first step plays on its first clock
+        sequencer.start();
+        writer.write_all(&[0xFA, 0xF8]).unwrap();
+        let (step, events) = receiver.recv_timeout(timeout).unwrap();
+        assert_eq!(step, 0);
+        assert!(matches!(events[..], [Event::NoteOn { .. }]));
+        sequencer.pause();
+        let (_, events) = receiver.recv_timeout(timeout).unwrap();
+        assert!(matches!(events[..], [Event::NoteOff { .. }]));
+        // the master moves to the third step and continues from there
+        sequencer.start();
+        writer.write_all(&[0xF2, 0x02, 0x00, 0xFB, 0xF8]).unwrap();
+        let (step, events) = receiver.recv_timeout(timeout).unwrap();
+        assert_eq!(step, 2);
+        assert!(matches!(events[..], [Event::NoteOn { .. }]));
+        sequencer.pause();
+    }
+
+    #[test]
+    fn edit_sounding_notes() {
+        let clock = ManualClock::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let tick = clock.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .on_step(move |_, events| {
+                let pulse = tick.get_ticks() - 1;
+                for event in events {
+                    match event {
+                        Event::NoteOn { .. } => log.lock().unwrap().push((pulse, true)),
+                        Event::NoteOff { .. } => log.lock().unwrap().push((pulse, false)),
+                        _ => {}
+                    }
+                }
+            })
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        let pattern = <Sequencer>::DEFAULT_STEPS * pulses;
+        let pitch = u7::try_from(60_u8).unwrap();
+        let note = |duration: u8| Note {
+            duration: Length::try_from(duration).unwrap(),
+            ..Note::from_pitch(pitch)
+        };
+        sequencer.add_note(u4::ZERO, u7::ZERO, note(4));
+        sequencer.start();
+
+        // a deleted note that is sounding still ends when due, only once
+        clock.advance(pulses);
+        sequencer.delete_note(u4::ZERO, u7::ZERO, note(0));
+        clock.advance(pattern);
+        assert_eq!(
+            events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![(0, true), (4 * pulses, false)]
+        );
+
+        // replacing a sounding note does not end it early or twice
+        let start = 2 * pattern;
+        sequencer.add_note(u4::ZERO, u7::ZERO, note(8));
+        clock.advance(start + 2 * pulses + 1 - clock.get_ticks());
+        sequencer.add_note(u4::ZERO, u7::ZERO, note(2));
+        clock.advance(pattern);
+        assert_eq!(
+            events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![
+                (start, true),
+                (start + 8 * pulses, false),
+                (start + pattern, true),
+                (start + pattern + 2 * pulses, false)
+            ]
+        );
+
+        // a later note of the same pitch ends the sounding one and lasts its
+        // own duration
+        let start = 4 * pattern;
+        clock.advance(start - clock.get_ticks());
+        sequencer.add_note(u4::ZERO, u7::ZERO, note(8));
+        sequencer.add_note(u4::ZERO, u7::try_from(4_u8).unwrap(), note(8));
+        clock.advance(pattern);
+        assert_eq!(
+            events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![
+                (start, true),
+                (start + 4 * pulses, false),
+                (start + 4 * pulses, true),
+                (start + 12 * pulses, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn random_edits() {
+        let clock = ManualClock::new();
+        let sounding = Arc::new(Mutex::new([[false; 128]; 4]));
+        let notes = sounding.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_swing(Swing::try_from(60).unwrap())
+            .on_step(move |_, events| {
+                let mut notes = notes.lock().unwrap();
+                for event in events {
+                    // every note off ends a sounding note, and a note is
+                    // never started again before it ends
+                    match event {
+                        Event::NoteOn { channel, pitch, .. } => {
+                            let note = &mut notes[usize::from(channel)][usize::from(pitch)];
+                            assert!(!*note);
+                            *note = true;
+                        }
+                        Event::NoteOff { channel, pitch } => {
+                            let note = &mut notes[usize::from(channel)][usize::from(pitch)];
+                            assert!(*note);
+                            *note = false;
+                        }
+                        _ => {}
+                    }
+                }
+            })
+            .build();
+        sequencer.start();
+        let mut rng = XorShift::new(1);
+        for _ in 0..2000 {
+            let track = u4::try_from((rng.next() % 4) as u8).unwrap();
+            let step = u7::try_from((rng.next() % 16) as u8).unwrap();
+            let note = Note {
+                pitch: u7::try_from((rng.next() % 4) as u8).unwrap(),
+                velocity: u7::ZERO,
+                duration: Length::try_from((rng.next() % 20) as u8).unwrap(),
+                offset: Nudge::try_from((rng.next() % 101) as i8 - 50).unwrap(),
+            };
+            match rng.next() % 3 {
+                0 => sequencer.add_note(track, step, note),
+                1 => sequencer.delete_note(track, step, note),
+                _ => sequencer.nudge_note(track, step, note),
+            }
+            clock.advance((rng.next() % 4) as usize);
+        }
+
+        // nothing is left sounding
         sequencer.pause();
-        assert_eq!(3 * Sequencer::TRACKS * Sequencer::STEPS / 2, x.load(SeqCst));
+        let sounding = sounding.lock().unwrap();
+        assert!(sounding.iter().flatten().all(|note| !note));
     }
 
     #[test]
@@ -393,12 +1866,10 @@ mod tests {
             Controller::Volume,
             Controller::Pan,
         ];
-        let tempo = NonZeroU8::new(250).unwrap();
-        let period = Duration::from_millis(60);
+        let clock = ManualClock::new();
         let x = Arc::new(AtomicUsize::new(0));
         let y = x.clone();
-        let mut sequencer = Sequencer::new()
-            .with_tempo(tempo)
+        let mut sequencer = Sequencer::with_clock(clock.clone())
             .on_step(move |step, events| {
                 let mut bitfield: u8 = 0;
                 for event in events {
@@ -422,12 +1893,13 @@ mod tests {
                 assert_eq!(bitfield, 0xF);
             })
             .build();
-        for track in 0..Sequencer::TRACKS {
-            for step in 0..Sequencer::STEPS {
+        let pulses = sequencer.get_pulses_per_step();
+        for track in 0..<Sequencer>::DEFAULT_TRACKS {
+            for step in 0..<Sequencer>::DEFAULT_STEPS {
                 for controller in &controllers {
                     sequencer.set_param(
-                        u2::try_from(track).unwrap(),
-                        u4::try_from(step).unwrap(),
+                        u4::try_from(track).unwrap(),
+                        u7::try_from(step).unwrap(),
                         Param {
                             controller: *controller,
                             value: u7::try_from(track + step).unwrap(),
@@ -437,10 +1909,10 @@ mod tests {
             }
         }
         sequencer.start();
-        sleep(Sequencer::STEPS as u32 * period - period / 2);
+        clock.advance(<Sequencer>::DEFAULT_STEPS * pulses);
         sequencer.pause();
         assert_eq!(
-            Sequencer::TRACKS * Sequencer::STEPS * controllers.len(),
+            <Sequencer>::DEFAULT_TRACKS * <Sequencer>::DEFAULT_STEPS * controllers.len(),
             x.load(SeqCst)
         );
     }
@@ -453,67 +1925,220 @@ mod tests {
             Controller::Volume,
             Controller::Pan,
         ];
-        let tempo = NonZeroU8::new(250).unwrap();
-        let period = Duration::from_millis(60);
-        let mut sequencer = Sequencer::new()
-            .with_tempo(tempo)
+        let clock = ManualClock::new();
+        let x = Arc::new(AtomicUsize::new(0));
+        let y = x.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
             .on_step(move |_, events| {
-                assert_eq!(true, events.is_empty());
+                assert!(events.is_empty());
+                let _ = y.fetch_add(1, SeqCst);
             })
             .build();
+        let pulses = sequencer.get_pulses_per_step();
 
         // add params
-        for track in 0..Sequencer::TRACKS {
-            for step in 0..Sequencer::STEPS {
+        for track in 0..<Sequencer>::DEFAULT_TRACKS {
+            for step in 0..<Sequencer>::DEFAULT_STEPS {
                 for controller in &controllers {
                     sequencer.set_param(
-                        u2::try_from(track).unwrap(),
-                        u4::try_from(step).unwrap(),
+                        u4::try_from(track).unwrap(),
+                        u7::try_from(step).unwrap(),
                         Param::from_controller(*controller),
                     );
                 }
             }
         }
         // remove params
-        for track in 0..Sequencer::TRACKS {
-            for step in 0..Sequencer::STEPS {
+        for track in 0..<Sequencer>::DEFAULT_TRACKS {
+            for step in 0..<Sequencer>::DEFAULT_STEPS {
                 for controller in &controllers {
                     sequencer.clear_param(
-                        u2::try_from(track).unwrap(),
-                        u4::try_from(step).unwrap(),
+                        u4::try_from(track).unwrap(),
+                        u7::try_from(step).unwrap(),
                         Param::from_controller(*controller),
                     );
                 }
             }
         }
         sequencer.start();
-        sleep(Sequencer::STEPS as u32 * period - period / 2);
+        clock.advance(<Sequencer>::DEFAULT_STEPS * pulses);
+        sequencer.pause();
+        assert_eq!(<Sequencer>::DEFAULT_STEPS, x.load(SeqCst));
+    }
+
+    #[test]
+    fn pause_note_offs() {
+        let clock = ManualClock::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_midi_clock(true)
+            .on_step(move |_, events| {
+                let events = events
+                    .into_iter()
+                    .filter(|event| *event != Event::TimingClock);
+                log.lock().unwrap().extend(events);
+            })
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        let pitch = u7::try_from(60_u8).unwrap();
+        let note = Note {
+            duration: Length::try_from(8_u8).unwrap(),
+            ..Note::from_pitch(pitch)
+        };
+        sequencer.add_note(u4::ZERO, u7::ZERO, note);
+        sequencer.start();
+        clock.advance(2 * pulses);
+        sequencer.pause();
+        let channel = u4::ZERO;
+        let velocity = u7::ZERO;
+        assert_eq!(
+            events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![
+                Event::Start,
+                Event::NoteOn {
+                    channel,
+                    pitch,
+                    velocity
+                },
+                Event::NoteOff { channel, pitch },
+                Event::Stop,
+            ]
+        );
+
+        // the note does not end again after starting
+        sequencer.start();
+        clock.advance(8 * pulses);
+        assert!(!events
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|event| matches!(event, Event::NoteOff { .. })));
+    }
+
+    #[test]
+    fn panic() {
+        let clock = ManualClock::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_tracks(NonZeroU8::new(2).unwrap())
+            .on_step(move |_, events| log.lock().unwrap().extend(events))
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        let note = Note {
+            duration: Length::try_from(4_u8).unwrap(),
+            ..Note::from_pitch(u7::ZERO)
+        };
+        sequencer.add_note(u4::ZERO, u7::ZERO, note);
+        sequencer.start();
+        clock.advance(pulses);
+        events.lock().unwrap().clear();
+
+        // channel mode messages for every track on the next tick, and no
+        // pending note offs
+        sequencer.panic(false);
+        assert!(events.lock().unwrap().is_empty());
+        clock.advance(1);
+        let channels = [u4::ZERO, u4::try_from(1_u8).unwrap()];
+        assert_eq!(
+            events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            channels
+                .iter()
+                .flat_map(|&channel| [
+                    Event::AllSoundOff { channel },
+                    Event::AllNotesOff { channel }
+                ])
+                .collect::<Vec<_>>()
+        );
+        clock.advance(4 * pulses);
+        assert!(events.lock().unwrap().is_empty());
+
+        // a note off for every pitch on every track, right away when paused
         sequencer.pause();
+        sequencer.panic(true);
+        let note_offs = events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| matches!(event, Event::NoteOff { .. }))
+            .count();
+        assert_eq!(note_offs, 2 * (u7::MAX as usize + 1));
+    }
+
+    #[test]
+    fn panic_note_on() {
+        let clock = ManualClock::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone())
+            .with_tracks(NonZeroU8::new(1).unwrap())
+            .on_step(move |_, events| log.lock().unwrap().extend(events))
+            .build();
+        let pulses = sequencer.get_pulses_per_step();
+        let note = Note {
+            duration: Length::try_from(1_u8).unwrap(),
+            ..Note::from_pitch(u7::ZERO)
+        };
+        sequencer.add_note(u4::ZERO, u7::ZERO, note);
+        sequencer.add_note(u4::ZERO, u7::try_from(1_u8).unwrap(), note);
+        sequencer.start();
+        clock.advance(pulses);
+        events.lock().unwrap().clear();
+
+        // the sounding note is silenced and forgotten, and a note that starts
+        // on the tick the panic is sent still ends
+        sequencer.panic(false);
+        assert!(events.lock().unwrap().is_empty()); // sent by the clock thread
+        clock.advance(pulses + 1);
+        let channel = u4::ZERO;
+        assert_eq!(
+            events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![
+                Event::AllSoundOff { channel },
+                Event::AllNotesOff { channel },
+                Event::NoteOn {
+                    channel,
+                    pitch: u7::ZERO,
+                    velocity: u7::ZERO
+                },
+                Event::NoteOff {
+                    channel,
+                    pitch: u7::ZERO
+                },
+            ]
+        );
     }
 
     #[test]
     fn pause_start() {
-        let tempo = NonZeroU8::new(250).unwrap();
-        let period = Duration::from_millis(60);
-        let mut sequencer = Sequencer::new().with_tempo(tempo).build();
+        let clock = ManualClock::new();
+        let x = Arc::new(AtomicUsize::new(0));
+        let y = x.clone();
+        let mut sequencer = Sequencer::with_clock(clock.clone()).build();
+        let pulses = sequencer.get_pulses_per_step();
         sequencer.start();
-        sleep(3 * period / 2);
+        clock.advance(2 * pulses);
         sequencer.pause();
+        clock.advance(2 * pulses); // paused sequencer does not step
         let last_step = sequencer.get_steps().0;
-        sequencer.on_step(move |step, _| assert_eq!(last_step, u4::try_from(step - 1).unwrap()));
+        assert_eq!(last_step, u7::try_from(1_u8).unwrap());
+        sequencer.on_step(move |step, _| {
+            assert_eq!(last_step, u7::try_from(step - 1).unwrap());
+            let _ = y.fetch_add(1, SeqCst);
+        });
         sequencer.start();
-        sleep(period / 2);
+        clock.advance(pulses);
         sequencer.pause();
+        assert_eq!(1, x.load(SeqCst));
     }
 
     #[test]
     fn while_running() {
-        let tempo = NonZeroU8::new(250).unwrap();
-        let period = Duration::from_millis(60);
+        let clock = ManualClock::new();
         let x = Arc::new(AtomicUsize::new(0));
         let y = x.clone();
-        let mut sequencer = Sequencer::new()
-            .with_tempo(tempo)
+        let mut sequencer = Sequencer::with_clock(clock.clone())
             .on_step(move |step, events| {
                 for event in events {
                     if let Event::NoteOn {
@@ -529,28 +2154,25 @@ mod tests {
                 }
             })
             .build();
+        let pulses = sequencer.get_pulses_per_step();
         sequencer.start();
-        sleep(period / 2);
+        clock.advance(pulses);
         for i in 0..u7::MAX {
-            let step =
-                u4::try_from((u8::from(sequencer.get_steps().0) + 1) % Sequencer::STEPS as u8)
-                    .unwrap();
-            println!(
-                "sent step {} pitch {} velocity {}",
-                u8::from(step),
-                u8::from(step),
-                i
-            );
+            let step = u7::try_from(
+                (u8::from(sequencer.get_steps().0) + 1) % <Sequencer>::DEFAULT_STEPS as u8,
+            )
+            .unwrap();
             sequencer.add_note(
-                u2::ZERO,
+                u4::ZERO,
                 step,
                 Note {
                     pitch: u7::try_from(u8::from(step)).unwrap(),
                     velocity: u7::try_from(i).unwrap(),
-                    duration: u4::ZERO,
+                    duration: Length::ZERO,
+                    offset: Nudge::ZERO,
                 },
             );
-            sleep(period);
+            clock.advance(pulses);
         }
         sequencer.pause();
         assert_eq!(u7::MAX as usize, x.load(SeqCst))
@@ -0,0 +1,123 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::{
+    atomic::{
+        AtomicUsize,
+        Ordering::{Acquire, Release},
+    },
+    Arc,
+};
+
+// fixed size ring shared by one Producer and one Consumer
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    head: AtomicUsize, // count of values popped, only written by the Consumer
+    tail: AtomicUsize, // count of values pushed, only written by the Producer
+}
+
+// Safety: a slot is only written by the Producer before tail is released and
+// only read by the Consumer after tail is acquired, and the reverse for head.
+// Producer and Consumer need &mut self, so each end is used by one thread at a time.
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+// sending half of a queue
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+// receiving half of a queue
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+// create a bounded single producer, single consumer queue. push and pop are
+// wait free and never allocate, so they are safe to use from a real time thread.
+// only Copy values are queued, so values left in the queue need no drop.
+pub fn queue<T: Copy>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let slots = (0..capacity.max(1))
+        .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+        .collect();
+    let ring = Arc::new(Ring {
+        slots,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (Producer { ring: ring.clone() }, Consumer { ring })
+}
+
+impl<T: Copy> Producer<T> {
+    // add a value to the back of the queue. returns the value if the queue is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let ring = &self.ring;
+        let tail = ring.tail.load(Acquire);
+        if tail.wrapping_sub(ring.head.load(Acquire)) == ring.slots.len() {
+            return Err(value);
+        }
+        let slot = &ring.slots[tail % ring.slots.len()];
+        // Safety: the Consumer does not read this slot until tail is released
+        unsafe { (*slot.get()).write(value) };
+        ring.tail.store(tail.wrapping_add(1), Release);
+        Ok(())
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    // take a value from the front of the queue. returns None if the queue is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        let ring = &self.ring;
+        let head = ring.head.load(Acquire);
+        if head == ring.tail.load(Acquire) {
+            return None;
+        }
+        let slot = &ring.slots[head % ring.slots.len()];
+        // Safety: the Producer wrote this slot before releasing tail and does
+        // not write it again until head is released
+        let value = unsafe { (*slot.get()).assume_init() };
+        ring.head.store(head.wrapping_add(1), Release);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::{spawn, yield_now};
+
+    #[test]
+    fn push_pop() {
+        let (mut producer, mut consumer) = queue(2);
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(producer.push(1), Ok(()));
+        assert_eq!(producer.push(2), Ok(()));
+        assert_eq!(producer.push(3), Err(3)); // full
+        assert_eq!(consumer.pop(), Some(1));
+        assert_eq!(producer.push(3), Ok(())); // wraps around
+        assert_eq!(consumer.pop(), Some(2));
+        assert_eq!(consumer.pop(), Some(3));
+        assert_eq!(consumer.pop(), None);
+    }
+
+    #[test]
+    fn threads() {
+        let count = 10_000;
+        let (mut producer, mut consumer) = queue(16);
+        let handle = spawn(move || {
+            let mut expected = 0;
+            while expected < count {
+                if let Some(value) = consumer.pop() {
+                    assert_eq!(value, expected); // in order, nothing lost
+                    expected += 1;
+                } else {
+                    yield_now();
+                }
+            }
+        });
+        for mut value in 0..count {
+            while let Err(full) = producer.push(value) {
+                value = full;
+                yield_now();
+            }
+        }
+        handle.join().unwrap();
+    }
+}
@@ -1,51 +1,6 @@
 use std::convert::TryFrom;
 use std::io::ErrorKind;
 
-#[allow(non_camel_case_types)]
-#[derive(Default, Clone, Copy, Debug)]
-pub struct u2(u8);
-
-impl u2 {
-    pub const MAX: u8 = 0x3;
-    pub const ZERO: Self = Self(0);
-}
-
-impl TryFrom<u8> for u2 {
-    type Error = ErrorKind;
-
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        if value <= Self::MAX {
-            Ok(Self(value))
-        } else {
-            Err(ErrorKind::InvalidInput)
-        }
-    }
-}
-
-impl TryFrom<usize> for u2 {
-    type Error = ErrorKind;
-
-    fn try_from(value: usize) -> Result<Self, Self::Error> {
-        if value <= Self::MAX.into() {
-            Ok(Self(value as u8))
-        } else {
-            Err(ErrorKind::InvalidInput)
-        }
-    }
-}
-
-impl From<u2> for u8 {
-    fn from(value: u2) -> Self {
-        value.0
-    }
-}
-
-impl From<u2> for usize {
-    fn from(value: u2) -> Self {
-        value.0 as usize
-    }
-}
-
 #[allow(non_camel_case_types)]
 #[derive(Default, Clone, Copy, Debug, PartialEq)]
 pub struct u4(u8);
@@ -156,11 +111,244 @@ impl From<u7> for usize {
     }
 }
 
-#[derive(Debug)]
+#[allow(non_camel_case_types)]
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct u14(u16);
+
+impl u14 {
+    pub const MAX: u16 = 0x3FFF;
+    pub const ZERO: Self = Self(0);
+}
+
+impl TryFrom<u16> for u14 {
+    type Error = ErrorKind;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        if value <= Self::MAX {
+            Ok(Self(value))
+        } else {
+            Err(ErrorKind::InvalidInput)
+        }
+    }
+}
+
+impl TryFrom<usize> for u14 {
+    type Error = ErrorKind;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        if value <= Self::MAX.into() {
+            Ok(Self(value as u16))
+        } else {
+            Err(ErrorKind::InvalidInput)
+        }
+    }
+}
+
+impl From<u14> for u16 {
+    fn from(value: u14) -> Self {
+        value.0
+    }
+}
+
+impl From<u14> for usize {
+    fn from(value: u14) -> Self {
+        value.0 as usize
+    }
+}
+
+// tempo in beats per minute. may be fractional.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tempo(f64);
+
+impl Tempo {
+    pub const MIN: f64 = 1.0;
+    pub const MAX: f64 = 999.0;
+}
+
+impl TryFrom<f64> for Tempo {
+    type Error = ErrorKind;
+
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        // also rejects NaN
+        if (Self::MIN..=Self::MAX).contains(&value) {
+            Ok(Self(value))
+        } else {
+            Err(ErrorKind::InvalidInput)
+        }
+    }
+}
+
+impl From<Tempo> for f64 {
+    fn from(value: Tempo) -> Self {
+        value.0
+    }
+}
+
+// swing in percent of a pair of steps before the second step plays.
+// 50 is straight, 66 is a triplet shuffle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Swing(u8);
+
+impl Swing {
+    pub const MIN: u8 = 50;
+    pub const MAX: u8 = 75;
+    pub const STRAIGHT: Self = Self(Self::MIN);
+}
+
+impl Default for Swing {
+    fn default() -> Self {
+        Self::STRAIGHT
+    }
+}
+
+impl TryFrom<u8> for Swing {
+    type Error = ErrorKind;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        if (Self::MIN..=Self::MAX).contains(&value) {
+            Ok(Self(value))
+        } else {
+            Err(ErrorKind::InvalidInput)
+        }
+    }
+}
+
+impl From<Swing> for u8 {
+    fn from(value: Swing) -> Self {
+        value.0
+    }
+}
+
+// timing offset of a note in percent of a step. negative is early.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Nudge(i8);
+
+impl Nudge {
+    pub const MIN: i8 = -50;
+    pub const MAX: i8 = 50;
+    pub const ZERO: Self = Self(0);
+}
+
+impl TryFrom<i8> for Nudge {
+    type Error = ErrorKind;
+
+    fn try_from(value: i8) -> Result<Self, Self::Error> {
+        if (Self::MIN..=Self::MAX).contains(&value) {
+            Ok(Self(value))
+        } else {
+            Err(ErrorKind::InvalidInput)
+        }
+    }
+}
+
+impl From<Nudge> for i8 {
+    fn from(value: Nudge) -> Self {
+        value.0
+    }
+}
+
+// speed of a track relative to the sequencer, as a multiplier and divider
+// of the step rate. 2 and 1 is double time, 1 and 2 is half time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    multiplier: u8,
+    divider: u8,
+}
+
+impl Rate {
+    pub const MIN: u8 = 1;
+    pub const MAX: u8 = 16;
+    pub const NORMAL: Self = Self {
+        multiplier: 1,
+        divider: 1,
+    };
+
+    pub fn multiplier(&self) -> u8 {
+        self.multiplier
+    }
+
+    pub fn divider(&self) -> u8 {
+        self.divider
+    }
+}
+
+impl Default for Rate {
+    fn default() -> Self {
+        Self::NORMAL
+    }
+}
+
+// from a multiplier and divider pair
+impl TryFrom<(u8, u8)> for Rate {
+    type Error = ErrorKind;
+
+    fn try_from(value: (u8, u8)) -> Result<Self, Self::Error> {
+        let (multiplier, divider) = value;
+        if (Self::MIN..=Self::MAX).contains(&multiplier)
+            && (Self::MIN..=Self::MAX).contains(&divider)
+        {
+            Ok(Self {
+                multiplier,
+                divider,
+            })
+        } else {
+            Err(ErrorKind::InvalidInput)
+        }
+    }
+}
+
+// order a track plays its steps in
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum Direction {
+    #[default]
+    Forward,
+    Reverse,
+    PingPong,       // back and forth, playing the first and last step once
+    PingPongRepeat, // back and forth, playing the first and last step twice
+    Random,         // any step
+    Drunk,          // one step forward or back at random
+}
+
+// length of a note in steps, up to the longest pattern. zero ends the note
+// on the tick it starts.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Length(u8);
+
+impl Length {
+    pub const MAX: u8 = 128;
+    pub const ZERO: Self = Self(0);
+}
+
+impl TryFrom<u8> for Length {
+    type Error = ErrorKind;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        if value <= Self::MAX {
+            Ok(Self(value))
+        } else {
+            Err(ErrorKind::InvalidInput)
+        }
+    }
+}
+
+impl From<Length> for u8 {
+    fn from(value: Length) -> Self {
+        value.0
+    }
+}
+
+impl From<Length> for usize {
+    fn from(value: Length) -> Self {
+        value.0 as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct Note {
     pub pitch: u7,
     pub velocity: u7,
-    pub duration: u4,
+    pub duration: Length, // no longer than the track loop when played
+    pub offset: Nudge,
 }
 
 impl Note {
@@ -168,12 +356,13 @@ impl Note {
         Self {
             pitch,
             velocity: u7::ZERO,
-            duration: u4::ZERO,
+            duration: Length::ZERO,
+            offset: Nudge::ZERO,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct Param {
     pub controller: Controller,
     pub value: u7,
@@ -189,6 +378,7 @@ impl Param {
 }
 
 // output from sequencer
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Event {
     NoteOn {
         channel: u4,
@@ -204,6 +394,25 @@ pub enum Event {
         controller: u7,
         value: u7,
     },
+    // channel mode messages to silence a channel
+    AllSoundOff {
+        channel: u4, // controller 120, cuts off release tails too
+    },
+    AllNotesOff {
+        channel: u4, // controller 123
+    },
+    // system real time messages for syncing other devices
+    TimingClock, // sent 24 times per quarter note
+    Start,
+    Continue,
+    Stop,
+    // position in MIDI beats (16th notes) since the start of the song
+    SongPosition {
+        position: u14,
+    },
+    // diagnostic, not MIDI. step data could not be read in time for this
+    // tick, so any note and controller events due were lost.
+    Dropped,
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -215,12 +424,13 @@ pub enum Controller {
 }
 
 impl Controller {
+    pub const COUNT: usize = 4; // number of controller types
     pub fn number(&self) -> u7 {
         match *self {
-            Self::Modulation => u7::try_from(1 as u8).unwrap(),
-            Self::Breath => u7::try_from(2 as u8).unwrap(),
-            Self::Volume => u7::try_from(7 as u8).unwrap(),
-            Self::Pan => u7::try_from(10 as u8).unwrap(),
+            Self::Modulation => u7::try_from(1_u8).unwrap(),
+            Self::Breath => u7::try_from(2_u8).unwrap(),
+            Self::Volume => u7::try_from(7_u8).unwrap(),
+            Self::Pan => u7::try_from(10_u8).unwrap(),
         }
     }
 }
@@ -0,0 +1,93 @@
+use sequencer::clock::ManualClock;
+use sequencer::sequencer::Sequencer;
+use sequencer::types::{u4, u7, Controller, Event, Length, Note, Nudge, Param, Swing};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering::SeqCst},
+    Arc,
+};
+
+// counts heap allocations made by the current thread, so allocations by other
+// tests running in parallel are not counted
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+#[test]
+fn steady_state() {
+    // ManualClock ticks on this thread, so the clock thread is this thread
+    let clock = ManualClock::new();
+    let count = Arc::new(AtomicUsize::new(0));
+    let events = count.clone();
+    let mut sequencer = Sequencer::with_clock(clock.clone())
+        .with_midi_clock(true)
+        .with_swing(Swing::try_from(66).unwrap())
+        .on_step_slice(move |_, slice: &[Event]| {
+            events.fetch_add(slice.len(), SeqCst);
+        })
+        .build();
+    let pulses = sequencer.get_pulses_per_step();
+    for track in 0..<Sequencer>::DEFAULT_TRACKS {
+        for step in 0..<Sequencer>::DEFAULT_STEPS {
+            let track = u4::try_from(track as u8).unwrap();
+            let step = u7::try_from(step as u8).unwrap();
+            let note = Note {
+                pitch: u7::try_from(u8::from(step)).unwrap(),
+                velocity: u7::try_from(100_u8).unwrap(),
+                duration: Length::try_from(3_u8).unwrap(),
+                offset: Nudge::try_from(-25).unwrap(),
+            };
+            sequencer.add_note(track, step, note);
+            let param = Param {
+                controller: Controller::Modulation,
+                value: u7::ZERO,
+            };
+            sequencer.set_param(track, step, param);
+        }
+    }
+    sequencer.start();
+    clock.advance(<Sequencer>::DEFAULT_STEPS * pulses); // warm up
+
+    let before = allocations();
+    clock.advance(4 * <Sequencer>::DEFAULT_STEPS * pulses);
+    assert_eq!(allocations(), before);
+
+    // edits are applied by the clock thread without allocating either
+    let pitch = u7::try_from(u7::MAX).unwrap();
+    sequencer.add_note(u4::ZERO, u7::ZERO, Note::from_pitch(pitch));
+    sequencer.delete_note(u4::ZERO, u7::ZERO, Note::from_pitch(u7::ZERO));
+    let param = Param::from_controller(Controller::Pan);
+    sequencer.set_param(u4::ZERO, u7::ZERO, param);
+    let before = allocations();
+    clock.advance(4 * <Sequencer>::DEFAULT_STEPS * pulses);
+    assert_eq!(allocations(), before);
+
+    assert!(count.load(SeqCst) > 0);
+}
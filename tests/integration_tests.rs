@@ -7,7 +7,7 @@ use std::time::Duration;
 fn notes() {
     Command::new("cargo").arg("build").output().unwrap(); // build first
     let mut sequencer = Command::new("target/debug/sequencer")
-        .args(&["1", "--midiout"])
+        .args(["1", "--midiout"])
         .stdin(Stdio::piped())
         .stderr(Stdio::piped())
         .stdout(Stdio::null())
@@ -21,19 +21,20 @@ fn notes() {
     stdin.write_all("start\n".as_bytes()).unwrap();
 
     sleep(Duration::from_millis(10));
-    let mut buf = [0u8; 6];
+    let mut buf = [0u8; 8];
     stderr.read_exact(&mut buf).unwrap();
     sequencer.kill().unwrap();
+    sequencer.wait().unwrap();
 
-    // one note_on and matching note_off
-    assert_eq!([0x90, 0x01, 0x01, 0x80, 0x01, 0x00], buf);
+    // start and timing clock, then one note_on and matching note_off
+    assert_eq!([0xFA, 0xF8, 0x90, 0x01, 0x01, 0x80, 0x01, 0x00], buf);
 }
 
 #[test]
 fn params() {
     Command::new("cargo").arg("build").output().unwrap(); // build first
     let mut sequencer = Command::new("target/debug/sequencer")
-        .args(&["1", "--midiout"])
+        .args(["1", "--midiout"])
         .stdin(Stdio::piped())
         .stderr(Stdio::piped())
         .stdout(Stdio::null())
@@ -49,10 +50,136 @@ fn params() {
     stdin.write_all("start\n".as_bytes()).unwrap();
 
     sleep(Duration::from_millis(10));
-    let mut buf = [0u8; 3];
+    let mut buf = [0u8; 5];
+    stderr.read_exact(&mut buf).unwrap();
+    sequencer.kill().unwrap();
+    sequencer.wait().unwrap();
+
+    // start and timing clock, then one controller change
+    assert_eq!([0xFA, 0xF8, 0xB0, 0x01, 0x7F], buf);
+}
+
+#[test]
+fn transport() {
+    Command::new("cargo").arg("build").output().unwrap(); // build first
+    let mut sequencer = Command::new("target/debug/sequencer")
+        .args(["1", "--midiout"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let stdin = sequencer.stdin.as_mut().unwrap();
+    let stderr = sequencer.stderr.as_mut().unwrap();
+
+    stdin
+        .write_all("start\npause\nstart\npause\nreset\n".as_bytes())
+        .unwrap();
+
+    sleep(Duration::from_millis(10));
+    let mut buf = [0u8; 12];
+    stderr.read_exact(&mut buf).unwrap();
+    sequencer.kill().unwrap();
+    sequencer.wait().unwrap();
+
+    // start, clock, stop, position 1, continue, clock, stop, position 0
+    assert_eq!(
+        [0xFA, 0xF8, 0xFC, 0xF2, 0x01, 0x00, 0xFB, 0xF8, 0xFC, 0xF2, 0x00, 0x00],
+        buf
+    );
+}
+
+#[test]
+fn midiin() {
+    // recorded master: start, two steps worth of timing clocks, stop
+    let path = std::env::temp_dir().join("sequencer_midiin.syx");
+    let mut stream = vec![0xFA];
+    stream.extend_from_slice(&[0xF8; 12]);
+    stream.push(0xFC);
+    std::fs::write(&path, stream).unwrap();
+
+    Command::new("cargo").arg("build").output().unwrap(); // build first
+    let mut sequencer = Command::new("target/debug/sequencer")
+        .args(["60", "--midiout", "--midiin", path.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let stdin = sequencer.stdin.as_mut().unwrap();
+    let stderr = sequencer.stderr.as_mut().unwrap();
+
+    stdin
+        .write_all("addnote 0 0 1 1 0\naddnote 0 1 2 1 0\nstart\n".as_bytes())
+        .unwrap();
+
+    sleep(Duration::from_millis(10));
+    let mut buf = [0u8; 20];
+    stderr.read_exact(&mut buf).unwrap();
+    sequencer.kill().unwrap();
+    sequencer.wait().unwrap();
+
+    // start and first step, five more clocks, then second step with its clock
+    assert_eq!(
+        [
+            0xFA, 0xF8, 0x90, 0x01, 0x01, 0x80, 0x01, 0x00, 0xF8, 0xF8, 0xF8, 0xF8, 0xF8, 0xF8,
+            0x90, 0x02, 0x01, 0x80, 0x02, 0x00
+        ],
+        buf
+    );
+}
+
+#[test]
+fn tracks() {
+    Command::new("cargo").arg("build").output().unwrap(); // build first
+    let mut sequencer = Command::new("target/debug/sequencer")
+        .args(["1", "--midiout", "--tracks", "16"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let stdin = sequencer.stdin.as_mut().unwrap();
+    let stderr = sequencer.stderr.as_mut().unwrap();
+
+    stdin.write_all("addnote 15 0 1 1 0\n".as_bytes()).unwrap();
+    stdin.write_all("start\n".as_bytes()).unwrap();
+
+    sleep(Duration::from_millis(10));
+    let mut buf = [0u8; 8];
+    stderr.read_exact(&mut buf).unwrap();
+    sequencer.kill().unwrap();
+    sequencer.wait().unwrap();
+
+    // last track plays on the last MIDI channel
+    assert_eq!([0xFA, 0xF8, 0x9F, 0x01, 0x01, 0x8F, 0x01, 0x00], buf);
+}
+
+#[test]
+fn panic() {
+    Command::new("cargo").arg("build").output().unwrap(); // build first
+    let mut sequencer = Command::new("target/debug/sequencer")
+        .args(["1", "--midiout", "--tracks", "1"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let stdin = sequencer.stdin.as_mut().unwrap();
+    let stderr = sequencer.stderr.as_mut().unwrap();
+
+    stdin.write_all("panic\n".as_bytes()).unwrap();
+
+    sleep(Duration::from_millis(10));
+    let mut buf = [0u8; 6];
     stderr.read_exact(&mut buf).unwrap();
     sequencer.kill().unwrap();
+    sequencer.wait().unwrap();
 
-    // one controller change
-    assert_eq!([0xB0, 0x01, 0x7F], buf);
+    // all sound off and all notes off on the only track
+    assert_eq!([0xB0, 0x78, 0x00, 0xB0, 0x7B, 0x00], buf);
 }